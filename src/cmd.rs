@@ -6,51 +6,157 @@
 extern crate colored;
 use colored::*;
 
+/*
+ * A quiet command is one read while executing, such as the contents of a
+ * register played back with @. An error in it throws away the rest of the
+ * command instead of ending the program, and the parsers give back None.
+ * Its keys are taken as they are, without key notation.
+ *
+ * With esc_inserts, inserted text ends at <Esc> or the end of the command
+ * as in Vim, instead of at a delimiter. Text that stops at <C-o> for a
+ * command leaves the delimiter its rest ends with in `resume`.
+ */
 pub struct Cmd {
-    pub orig     : String,
-    pub working  : String,
-    pub consumed : usize
+    pub orig        : String,
+    pub working     : String,
+    pub consumed    : usize,
+    pub quiet       : bool,
+    pub esc_inserts : bool,
+    pub resume      : Option<Option<char>>
 }
 
 impl Cmd {
-    pub fn new(cmd : &String) -> Cmd {
+    pub fn new(cmd : &str) -> Cmd {
+        Cmd::keys(&expand_keys(cmd))
+    }
+
+    pub fn quiet(cmd : &str) -> Cmd {
+        Cmd { quiet : true, ..Cmd::keys(cmd) }
+    }
+
+    fn keys(keys : &str) -> Cmd {
         Cmd {
-            orig     : cmd.clone(),
-            working  : cmd.clone(),
-            consumed : 0
+            orig        : keys.to_owned(),
+            working     : keys.to_owned(),
+            consumed    : 0,
+            quiet       : false,
+            esc_inserts : false,
+            resume      : None
         }
     }
 
-    pub fn len(&self) -> usize { self.working.len() }
+    pub fn is_empty(&self) -> bool { self.working.is_empty() }
+
+    pub fn peek(&self, n : usize) -> Option<char> { self.working.chars().nth(n) }
 
-    pub fn first(&self) -> char {
-        if let Some(c) = self.working.chars().nth(0) {
+    /* past the end of a quiet command, '\0' after the error */
+    pub fn first(&mut self) -> char {
+        if let Some(c) = self.working.chars().next() {
             c
         } else {
-            let msg = String::from("unexpected end of command input");
-            self.err(&msg);
-            unreachable!()
+            self.err("unexpected end of command input");
+            '\0'
         }
     }
 
     pub fn consume(&mut self) -> char {
         let c = self.first();
-        self.working.remove(0);
-        self.consumed += 1;
+        if !self.working.is_empty() {
+            self.working.remove(0);
+            self.consumed += 1;
+        }
         c
     }
 
-    pub fn err(&self, msg : &String) {
+    /* the next character, which the command can't end without */
+    pub fn take(&mut self) -> Option<char> {
+        if self.is_empty() {
+            self.err("unexpected end of command input");
+            return None;
+        }
+        Some(self.consume())
+    }
+
+    /* the character consumed last */
+    pub fn last(&self) -> Option<char> {
+        self.consumed.checked_sub(1).and_then(|n| self.orig.chars().nth(n))
+    }
+
+    pub fn err(&mut self, msg : &str) {
+        if self.quiet {
+            self.working.clear();
+            return;
+        }
+
         eprintln!("norm: {}", msg.red());
-        let used : String = self.orig.chars().take(self.consumed).collect();
-        eprintln!("      Here: '{}{}'", used, self.working);
+        let used = show_keys(&self.orig.chars().take(self.consumed).collect::<String>());
+        let rest = show_keys(&self.working);
+        eprintln!("      Here: '{}{}'", used, rest);
         eprintln!("             {}{}{}",
-                  std::iter::repeat("~").take(self.consumed).collect::<String>().green(),
+                  "~".repeat(used.chars().count()).green(),
                   "^".green(),
-                  std::iter::repeat("~").take(
-                      if self.consumed < self.orig.len() {
-                          self.orig.len() - self.consumed - 1
-                      } else { 0 }).collect::<String>().green());
+                  "~".repeat(rest.chars().count().saturating_sub(1)).green());
         std::process::exit(1);
     }
 }
+
+/*
+ * Translates Vim's key notation, such as <Esc>, <CR> or <C-a>, into the
+ * characters those keys type. A '<' that doesn't start a known key name
+ * is kept as it is, and <lt> stands for a '<'.
+ */
+pub fn expand_keys(s : &str) -> String {
+    let mut out  = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find('<') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        match rest.find('>').and_then(|end| Some((key_code(&rest[1..end])?, end))) {
+            Some((c, end)) => { out.push(c); rest = &rest[end + 1..]; },
+            None           => { out.push('<'); rest = &rest[1..]; },
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn key_code(name : &str) -> Option<char> {
+    let lower = name.to_ascii_lowercase();
+    if let Some(key) = lower.strip_prefix("c-") {
+        let mut chars = key.chars();
+        return match (chars.next(), chars.next()) {
+            (Some('?'), None)                                 => Some('\x7f'),
+            (Some(c), None) if c.is_ascii_lowercase()         => Some((c as u8 - b'`') as char),
+            (Some(c), None) if "@[\\]^_".contains(c)          => Some((c as u8 - b'@') as char),
+            _                                                 => None,
+        };
+    }
+    match lower.as_str() {
+        "esc"                        => Some('\x1b'),
+        "cr" | "return" | "enter"    => Some('\r'),
+        "nl" | "newline" | "lf"      => Some('\n'),
+        "tab"                        => Some('\t'),
+        "space"                      => Some(' '),
+        "lt"                         => Some('<'),
+        "bslash"                     => Some('\\'),
+        "bar"                        => Some('|'),
+        "bs" | "backspace"           => Some('\x08'),
+        "del" | "delete"             => Some('\x7f'),
+        _                            => None,
+    }
+}
+
+/* control characters back in key notation, for messages */
+pub fn show_keys(s : &str) -> String {
+    s.chars().map(|c| match c {
+        '\x1b'                 => "<Esc>".to_owned(),
+        '\r'                   => "<CR>".to_owned(),
+        '\n'                   => "<NL>".to_owned(),
+        '\t'                   => "<Tab>".to_owned(),
+        '\x08'                 => "<BS>".to_owned(),
+        '\x7f'                 => "<Del>".to_owned(),
+        '\x01'..='\x1a'        => format!("<C-{}>", (c as u8 + b'`') as char),
+        '\0' | '\x1b'..='\x1f' => format!("<C-{}>", (c as u8 + b'@') as char),
+        c                      => c.to_string(),
+    }).collect()
+}
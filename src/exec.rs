@@ -6,17 +6,23 @@
 #![allow(non_camel_case_types)]
 
 use crate::op::*;
+use crate::cmd::Cmd;
+use crate::parse::parse_command;
+use crate::reg::{Register, Registers};
+use crate::settings::{NrFormats, Settings};
 
 extern crate page_size;
 extern crate rayon;
+extern crate regex;
 
 use std::io::{self, BufRead};
 use rayon::prelude::*;
 use std::sync::{Arc, Mutex};
+use regex::{Captures, Regex, RegexBuilder};
 
 struct Outputer {
     pub allow  : usize,
-    pub buf_wr : Box<io::Write + Send>
+    pub buf_wr : Box<dyn io::Write + Send>
 }
 
 impl Outputer {
@@ -28,169 +34,763 @@ impl Outputer {
     }
 }
 
+/* how deeply registers may play each other before playback gives up */
+const MAX_PLAY_DEPTH : usize = 1000;
+
+#[derive(Copy, Clone, PartialEq, PartialOrd)]
+struct Pos {
+    line : usize,
+    col  : usize,
+}
+
+/*
+ * A charwise range runs from beg up to, but not including, end. A linewise
+ * range covers every line from beg.line through end.line.
+ */
+#[derive(Copy, Clone)]
+struct Range {
+    beg      : Pos,
+    end      : Pos,
+    linewise : bool,
+}
+
+/*
+ * What a character typed in Replace mode did, for <BS> to take back: it
+ * overwrote a character, went on past the end of the line, or broke the
+ * line.
+ */
+#[derive(Copy, Clone)]
+enum Replaced {
+    Char(char),
+    Past,
+    Break,
+}
+
+/*
+ * The pattern, direction and offset n and N repeat.
+ */
+#[derive(Clone)]
+struct LastSearch {
+    re       : Regex,
+    backward : bool,
+    offset   : SearchOffset,
+}
+
+/*
+ * What & and g& repeat; `rep` already has any ~ replaced.
+ */
+#[derive(Clone)]
+struct LastSub {
+    re    : Regex,
+    rep   : String,
+    flags : SubFlags,
+}
+
+/*
+ * One undo step: the lines from `top` that a change replaced, the lines it
+ * put in their place, and where the change started.
+ */
+#[derive(Clone)]
+struct Change {
+    top    : usize,
+    old    : Vec<String>,
+    new    : Vec<String>,
+    cursor : Pos,
+    empty  : (bool, bool),
+    marks  : [Option<Pos>; 26],
+}
+
+/* the buffer as it was before a change, for making an undo step of it */
+struct Snapshot {
+    lines  : Vec<String>,
+    empty  : bool,
+    cursor : Pos,
+    marks  : [Option<Pos>; 26],
+}
+
+/*
+ * What U puts back: a line as it was before the latest changes to it, and
+ * the column to go to.
+ */
+#[derive(Clone)]
+struct LineUndo {
+    line : usize,
+    text : String,
+    col  : usize,
+}
+
+/*
+ * A worker edits a buffer of lines: either each input line on its own, or
+ * the whole input at once in buffer mode. `line` and `cursor` locate the
+ * cursor in that buffer, and `want` is the column vertical motions aim for.
+ */
 struct Worker {
-    pos    : usize,
-    cursor : usize,
-    pb     : String,
-    last_f : Option<Motion>,
-    last_o : Option<Op>,
-    lines  : Vec<String>
+    settings : Settings,
+    pos      : usize,
+    line     : usize,
+    cursor   : usize,
+    want     : usize,
+    failed   : bool,
+    empty    : bool,
+    regs     : Registers,
+    last_f   : Option<Motion>,
+    last_o   : Option<Op>,
+    last_s   : Option<LastSearch>,
+    last_sub : Option<LastSub>,
+    last_at  : Option<char>,
+    undo     : Vec<Change>,
+    redo     : Vec<Change>,
+    uline    : Option<LineUndo>,
+    marks    : [Option<Pos>; 26],
+    op_start : Option<Pos>,
+    at_eol   : Option<usize>,
+    want_set : bool,
+    last_insert : Option<Pos>,
+    depth    : usize,
+    undoable : bool,
+    block    : Option<Snapshot>,
+    track_line : bool,
+    pending  : bool,
+    input    : Vec<String>,
+    lines    : Vec<String>,
+    output   : Vec<String>,
 }
 
 impl Worker {
+    fn new(settings : Settings, pos : usize, input : Vec<String>) -> Worker {
+        Worker {
+            settings,
+            pos,
+            line     : 0,
+            cursor   : 0,
+            want     : 0,
+            failed   : false,
+            empty    : false,
+            regs     : Registers::default(),
+            last_f   : None,
+            last_o   : None,
+            last_s   : None,
+            last_sub : None,
+            last_at  : None,
+            undo     : Vec::new(),
+            redo     : Vec::new(),
+            uline    : None,
+            marks    : [None; 26],
+            op_start : None,
+            at_eol   : None,
+            want_set : false,
+            last_insert : None,
+            depth    : 0,
+            undoable : false,
+            block    : None,
+            track_line : false,
+            pending  : false,
+            input,
+            lines    : Vec::new(),
+            output   : Vec::new(),
+        }
+    }
+
     fn execute_Beg(&mut self, _op : &Op, _line : usize) {
         self.cursor = 0;
     }
 
-    fn execute_End(&mut self, _op : &Op, line : usize) {
-        if self.lines[line].len() > 0 {
-            self.cursor = self.lines[line].len() - 1;
+    /*
+     * The line a count takes $ and g_ to: count - 1 lines down, or as far
+     * as there are lines, like j.
+     */
+    fn count_down(&self, line : usize, count : u32) -> Option<usize> {
+        let last = self.lines.len() - 1;
+        match count {
+            1                    => Some(line),
+            _ if line == last    => None,
+            _                    => Some((line + count as usize - 1).min(last)),
         }
     }
 
-    fn execute_Left(&mut self, _op : &Op, _line : usize) {
-        if self.cursor > 0 {
-            self.cursor -= 1; 
-        }
+    fn execute_End(&mut self, op : &Op, line : usize) {
+        let target = match self.count_down(line, op.get_repeat()) {
+            Some(l) => l,
+            None    => { self.failed = true; return; },
+        };
+
+        self.line   = target;
+        self.cursor = self.lines[target].chars().count().saturating_sub(1);
+    }
+
+    fn execute_FirstNonBlank(&mut self, _op : &Op, line : usize) {
+        self.cursor = first_non_blank(&self.lines[line]);
+    }
+
+    /* g_ stays on the first column of a blank line */
+    fn execute_LastNonBlank(&mut self, op : &Op, line : usize) {
+        let target = match self.count_down(line, op.get_repeat()) {
+            Some(l) => l,
+            None    => { self.failed = true; return; },
+        };
+
+        self.line   = target;
+        self.cursor = self.lines[target].chars().rev()
+                          .skip_while(|c| c.is_whitespace()).count().saturating_sub(1);
+    }
+
+    /*
+     * {count}| goes to the character shown at that display column, with
+     * tabs expanded to the tabstop, or to the end of a shorter line.
+     */
+    fn execute_Column(&mut self, op : &Op, line : usize) {
+        let n = match op.get_motion().mot {
+            SingleMotion::Column { n } => n as usize,
+            _                          => panic!("internal error: execute_Column() -- missing column")
+        };
+
+        let chars : Vec<char> = self.lines[line].chars().collect();
+        self.cursor = col_at_vcol(&chars, n - 1, self.settings.tabstop);
+        self.clamp_cursor(line);
     }
-    
-    fn execute_Right(&mut self, _op : &Op, line : usize) {
-        if self.cursor < self.lines[line].len() - 1 {
-            self.cursor += 1;
+
+    /*
+     * % goes to the partner of the first bracket from 'matchpairs' or
+     * word from 'matchwords' at or after the cursor on the line. Like
+     * matchit, an operator takes in all of a word % goes forward to.
+     */
+    fn execute_Match(&mut self, _op : &Op, line : usize) {
+        let chars : Vec<char> = self.lines[line].chars().collect();
+        let words             = line_keywords(&chars, &self.settings.matchwords);
+
+        let word    = words.into_iter().find(|k| k.end > self.cursor);
+        let bracket = (self.cursor..chars.len()).find(|&i| self.partner(chars[i]).is_some());
+
+        let target = match (word, bracket) {
+            (Some(k), Some(b)) if k.beg <= b => self.match_word(line, &k),
+            (Some(k), None)                  => self.match_word(line, &k),
+            (_, Some(b))                     => self.match_bracket(line, &chars, b).map(|p| (p, p.col)),
+            (None, None)                     => None,
+        };
+
+        match target {
+            Some((p, last)) if self.pending && p > (Pos{ line, col : self.cursor }) => (self.line, self.cursor) = (p.line, last),
+            Some((p, _))                                                        => (self.line, self.cursor) = (p.line, p.col),
+            None                                                                => self.failed = true,
         }
     }
-   
-    fn execute_Word(&mut self, _op : &Op, line : usize) {
-        if self.lines[line].len() > 0 {
-            let c = self.lines[line].chars().nth(self.cursor).unwrap();
-
-            if c.is_alphanumeric() || c == '_' {
-                while let Some(ch) = self.lines[line].chars().nth(self.cursor) {
-                    if ch.is_alphanumeric() || ch == '_' { self.cursor += 1; }
-                    else                                 { break; }
-                }
-                while let Some(ch) = self.lines[line].chars().nth(self.cursor) {
-                    if ch.is_whitespace() { self.cursor += 1; } else { break; }
-                }
-            } else if c.is_whitespace() {
-                while let Some(ch) = self.lines[line].chars().nth(self.cursor) {
-                    if ch.is_whitespace() { self.cursor += 1; } else { break; }
+
+    /* the other half of a bracket pair, and whether it comes after */
+    fn partner(&self, c : char) -> Option<(char, bool)> {
+        self.settings.matchpairs.iter().find_map(|&(open, close)| {
+            if c == open           { Some((close, true)) }
+            else if c == close     { Some((open, false)) }
+            else                   { None }
+        })
+    }
+
+    /*
+     * Brackets nest, and only count when they are escaped with a backslash
+     * and inside double quotes the same way as the one we start from.
+     */
+    fn match_bracket(&self, line : usize, chars : &[char], i : usize) -> Option<Pos> {
+        let c                 = chars[i];
+        let (other, forward)  = self.partner(c)?;
+        let escaped           = is_escaped(chars, i);
+        let quoted            = quote_mask(chars)[i];
+
+        let mut l     = line;
+        let mut cur   = chars.to_vec();
+        let mut mask  = quote_mask(&cur);
+        let mut k     = i as isize;
+        let mut depth = 0;
+
+        loop {
+            k += if forward { 1 } else { -1 };
+            while k < 0 || k >= cur.len() as isize {
+                if forward {
+                    if l + 1 >= self.lines.len()    { return None; }
+                    l += 1;
+                } else {
+                    if l == 0    { return None; }
+                    l -= 1;
                 }
+                cur  = self.lines[l].chars().collect();
+                mask = quote_mask(&cur);
+                k    = if forward { 0 } else { cur.len() as isize - 1 };
+            }
+
+            let ku = k as usize;
+            let ch = cur[ku];
+            if (ch != c && ch != other) || is_escaped(&cur, ku) != escaped || mask[ku] != quoted {
+                continue;
+            }
+            if ch == c {
+                depth += 1;
+            } else if depth == 0 {
+                return Some(Pos{ line : l, col : ku });
             } else {
-                while let Some(ch) = self.lines[line].chars().nth(self.cursor) {
-                    if ch.is_alphanumeric() || ch == '_'    { break; }
-                    self.cursor += 1;
-                }
+                depth -= 1;
             }
         }
     }
-    
-    fn execute_Back(&mut self, _op : &Op, line : usize) {
-        if self.lines[line].len() > 0 && self.cursor > 0 {
-            let c = self.lines[line].chars().nth(self.cursor - 1).unwrap();
 
-            if c.is_alphanumeric() || c == '_' {
-                while let Some(ch) = self.lines[line].chars().nth(self.cursor - 1) {
-                    if ch.is_alphanumeric() || ch == '_' { self.cursor -= 1; }
-                    else                                 { break; }
-                    if self.cursor == 0 { break; }
-                }
-            } else if c.is_whitespace() {
-                while let Some(ch) = self.lines[line].chars().nth(self.cursor - 1) {
-                    if ch.is_whitespace() { self.cursor -= 1; } else { break; }
-                    if self.cursor == 0 { break; }
-                }
-                if self.cursor > 0 {
-                    while let Some(ch) = self.lines[line].chars().nth(self.cursor - 1) {
-                        if ch.is_alphanumeric() || ch == '_' { self.cursor -= 1; }
-                        else                                 { break; }
-                        if self.cursor == 0 { break; }
+    /*
+     * Like matchit: the first word of a group and the ones in between go
+     * forward to the next word of the group at the same depth, and the
+     * last word goes back to the first. Gives back where the word found
+     * starts and the column of its last character.
+     */
+    fn match_word(&self, line : usize, kw : &Keyword) -> Option<(Pos, usize)> {
+        let last    = self.settings.matchwords[kw.group].len() - 1;
+        let forward = kw.role != last;
+        let mut depth = 0;
+
+        let lines : Box<dyn Iterator<Item = usize>> = if forward {
+            Box::new(line..self.lines.len())
+        } else {
+            Box::new((0..=line).rev())
+        };
+
+        for l in lines {
+            let chars    = self.lines[l].chars().collect::<Vec<char>>();
+            let mut here = line_keywords(&chars, &self.settings.matchwords);
+            here.retain(|k| k.group == kw.group
+                         && (l != line || (forward && k.beg > kw.beg) || (!forward && k.beg < kw.beg)));
+            if !forward    { here.reverse(); }
+
+            for k in here {
+                if forward {
+                    if k.role == 0 {
+                        depth += 1;
+                    } else if depth == 0 {
+                        return Some((Pos{ line : l, col : k.beg }, k.end - 1));
+                    } else if k.role == last {
+                        depth -= 1;
                     }
-                }
-            } else {
-                while let Some(ch) = self.lines[line].chars().nth(self.cursor - 1) {
-                    if ch.is_alphanumeric() || ch == '_'    { break; }
-                    self.cursor -= 1;
-                    if self.cursor == 0 { break; }
+                } else if k.role == last {
+                    depth += 1;
+                } else if k.role == 0 {
+                    if depth == 0    { return Some((Pos{ line : l, col : k.beg }, k.end - 1)); }
+                    depth -= 1;
                 }
             }
         }
+        None
     }
 
-    fn execute_Till(&mut self, op : &Op, line : usize) {
-        let c = op.get_c();
+    /* {count}% goes to the line count percent of the way through */
+    fn execute_Percent(&mut self, op : &Op, _line : usize) {
+        let n = match op.get_motion().mot {
+            SingleMotion::Percent { n } => n as usize,
+            _                           => panic!("internal error: execute_Percent() -- missing count")
+        };
 
-        if let Some(next) = self.lines[line].chars().nth(self.cursor + 1) {
-            let advance = if next == c { 2 } else { 1 };
-            let remaining : String = self.lines[line].chars().skip(self.cursor + advance).collect();
+        if n > 100 {
+            self.failed = true;
+            return;
+        }
+        self.line   = (n * self.lines.len()).div_ceil(100).max(1) - 1;
+        self.cursor = first_non_blank(&self.lines[self.line]);
+    }
 
-            if let Some(p) = remaining.find(c)    { self.cursor += p + (advance - 1); }
+    /*
+     * h, l, j and k go as far as they can, and only fail when they can't
+     * move at all.
+     */
+    fn execute_Left(&mut self, op : &Op, _line : usize) {
+        if self.cursor == 0 {
+            self.failed = true;
+            return;
         }
+        self.cursor = self.cursor.saturating_sub(op.get_repeat() as usize);
+    }
 
-        self.last_f = Some(op.get_motion().clone()); 
+    fn execute_Right(&mut self, op : &Op, line : usize) {
+        let len = self.lines[line].chars().count();
+        if self.cursor + 1 >= len {
+            self.failed = true;
+            return;
+        }
+        self.cursor = (self.cursor + op.get_repeat() as usize).min(len - 1);
     }
-    
-    fn execute_BackTill(&mut self, op : &Op, line : usize) {
-        let c = op.get_c();
-    
-        if self.cursor > 0 {
-            let s : String = self.lines[line].chars().take(self.cursor).collect();
-        
-            if let Some(p) = s.rfind(c)    { self.cursor = p + 1; }
+   
+    /*
+     * The word motions step through the whole buffer like Vim's, with the
+     * same rules for when they fail. An operator's w stops at the end of
+     * the line on its last count instead of going on to the next one.
+     */
+    fn execute_Word(&mut self, op : &Op, line : usize) {
+        let m      = op.get_motion();
+        let big    = matches!(m.mot, SingleMotion::BigWord);
+        let mut wc = WordCursor::new(&self.lines, Pos{ line, col : self.cursor }, big);
+        let ok     = wc.fwd_word(m.repeat, self.pending);
+        let pos    = wc.pos;
+
+        (self.line, self.cursor) = (pos.line, pos.col);
+        if !self.pending {
+            self.clamp_cursor(self.line);
+            self.failed = !ok;
         }
+    }
+
+    fn execute_Back(&mut self, op : &Op, line : usize) {
+        let m      = op.get_motion();
+        let big    = matches!(m.mot, SingleMotion::BigBack);
+        let mut wc = WordCursor::new(&self.lines, Pos{ line, col : self.cursor }, big);
+        let ok     = wc.bck_word(m.repeat);
+        let pos    = wc.pos;
+
+        (self.line, self.cursor) = (pos.line, pos.col);
+        self.failed = !ok;
+    }
+
+    fn execute_WordEnd(&mut self, op : &Op, line : usize) {
+        let m      = op.get_motion();
+        let big    = matches!(m.mot, SingleMotion::BigWordEnd);
+        let mut wc = WordCursor::new(&self.lines, Pos{ line, col : self.cursor }, big);
+        let ok     = wc.end_word(m.repeat, false, false);
+        let pos    = wc.pos;
+
+        (self.line, self.cursor) = (pos.line, pos.col);
+        self.clamp_cursor(self.line);
+        self.failed = !ok && !self.pending;
+    }
 
-        self.last_f = Some(op.get_motion().clone()); 
+    fn execute_WordEndBack(&mut self, op : &Op, line : usize) {
+        let m      = op.get_motion();
+        let big    = matches!(m.mot, SingleMotion::BigWordEndBack);
+        let mut wc = WordCursor::new(&self.lines, Pos{ line, col : self.cursor }, big);
+        let ok     = wc.bckend_word(m.repeat);
+        let pos    = wc.pos;
+
+        (self.line, self.cursor) = (pos.line, pos.col);
+        self.clamp_cursor(self.line);
+        self.failed = !ok;
+    }
+
+    /*
+     * The character, direction and till-ness of an f, F, t or T motion, or
+     * of the one ; and , repeat, with , going the other way.
+     */
+    fn char_search(&self, m : &Motion) -> Option<(char, bool, bool)> {
+        match m.mot {
+            SingleMotion::Till     { c } => Some((c, true,  true)),
+            SingleMotion::BackTill { c } => Some((c, false, true)),
+            SingleMotion::Find     { c } => Some((c, true,  false)),
+            SingleMotion::BackFind { c } => Some((c, false, false)),
+            SingleMotion::FindNext { reverse } => {
+                let (c, forward, till) = self.char_search(self.last_f.as_ref()?)?;
+                Some((c, forward != reverse, till))
+            },
+            _ => None
+        }
     }
 
+    /*
+     * Follows Vim's searchc(): the count'th occurrence of the character
+     * on the line, or nothing at all if there aren't that many. Repeating
+     * a t or T once skips a match right next to the cursor so that it
+     * doesn't get stuck.
+     */
     fn execute_Find(&mut self, op : &Op, line : usize) {
-        let c                  = op.get_c();
-        let remaining : String = self.lines[line].chars().skip(self.cursor + 1).collect();
-        
-        if let Some(p) = remaining.find(c)    { self.cursor += p + 1; }
+        let m = op.get_motion();
+        let (c, forward, till) = match self.char_search(m) {
+            Some(s) => s,
+            None    => { self.failed = true; return; },
+        };
+
+        if !matches!(m.mot, SingleMotion::FindNext { .. }) {
+            self.last_f = Some(m.clone());
+        }
+        let mut stop = !(matches!(m.mot, SingleMotion::FindNext { .. }) && m.repeat == 1 && till);
+
+        let chars : Vec<char> = self.lines[line].chars().collect();
+        let mut col           = self.cursor;
+        for _ in 0..m.repeat {
+            loop {
+                if forward {
+                    col += 1;
+                    if col >= chars.len() {
+                        self.failed = true;
+                        return;
+                    }
+                } else {
+                    if col == 0 {
+                        self.failed = true;
+                        return;
+                    }
+                    col -= 1;
+                }
+                if chars[col] == c && stop    { break; }
+                stop = true;
+            }
+        }
+
+        if till {
+            col = if forward { col - 1 } else { col + 1 };
+        }
+        self.cursor = col;
+    }
+
+    fn want_col(&self) -> usize {
+        let len = self.lines[self.line].chars().count();
+        self.want.min(len.saturating_sub(1))
+    }
+
+    fn execute_Down(&mut self, op : &Op, line : usize) {
+        if line + 1 >= self.lines.len() {
+            self.failed = true;
+            return;
+        }
+        self.line   = (line + op.get_repeat() as usize).min(self.lines.len() - 1);
+        self.cursor = self.want_col();
+    }
+
+    fn execute_Up(&mut self, op : &Op, line : usize) {
+        if line == 0 {
+            self.failed = true;
+            return;
+        }
+        self.line   = line.saturating_sub(op.get_repeat() as usize);
+        self.cursor = self.want_col();
+    }
+
+    fn execute_GotoLine(&mut self, op : &Op, _line : usize) {
+        let n = match op.get_motion().mot {
+            SingleMotion::GotoLine { n } => n as usize,
+            _                            => panic!("internal error: execute_GotoLine() -- missing line")
+        };
+
+        let last    = self.lines.len() - 1;
+        self.line   = if n == 0 { last } else { (n - 1).min(last) };
+        self.cursor = first_non_blank(&self.lines[self.line]);
+    }
+
+    /*
+     * ` goes to the mark itself and ' to the first non-blank of its line.
+     * Jumping to a mark that isn't set, or was deleted with its line, fails.
+     */
+    fn execute_GotoMark(&mut self, op : &Op, _line : usize) {
+        let (c, exact) = match op.get_motion().mot {
+            SingleMotion::Mark     { c } => (c, true),
+            SingleMotion::MarkLine { c } => (c, false),
+            _                            => panic!("internal error: execute_GotoMark() -- missing mark")
+        };
+
+        let p = match self.marks[(c as u8 - b'a') as usize] {
+            Some(p) if p.line < self.lines.len() => p,
+            _                                    => { self.failed = true; return; },
+        };
+
+        self.line = p.line;
+        if exact {
+            self.cursor = p.col;
+            self.clamp_cursor(p.line);
+        } else {
+            self.cursor = first_non_blank(&self.lines[p.line]);
+        }
+    }
+
+    fn execute_Mark(&mut self, op : &Op, line : usize) {
+        if let Op::Mark{ c } = op {
+            self.marks[(*c as u8 - b'a') as usize] = Some(Pos{ line, col : self.cursor });
+        }
+    }
+
+    /*
+     * All matches of `re` in a line as character ranges, including ones
+     * that overlap.
+     */
+    fn line_matches(&self, re : &Regex, line : usize) -> Vec<(usize, usize)> {
+        let s       = &self.lines[line];
+        let col     = |b : usize| s[..b].chars().count();
+        let mut v   = Vec::new();
+        let mut at  = 0;
+
+        while at <= s.len() {
+            match re.find_at(s, at) {
+                Some(m) => {
+                    v.push((col(m.start()), col(m.end())));
+                    at = m.start() + s[m.start()..].chars().next().map_or(1, |c| c.len_utf8());
+                },
+                None    => break,
+            }
+        }
+        v
+    }
+
+    /*
+     * Finds the next match after `from`, or the previous one before it,
+     * wrapping around the ends of the buffer. With an end offset matches
+     * are compared by their last character instead of their first.
+     */
+    fn find_match(&self, re : &Regex, backward : bool, by_end : bool, from : Pos) -> Option<(usize, usize, usize)> {
+        let n    = self.lines.len();
+        let key  = |m : &(usize, usize)| if by_end { m.1.max(m.0 + 1) - 1 } else { m.0 };
+
+        for i in 0..=n {
+            let line    = if backward { (from.line + n * 2 - i) % n } else { (from.line + i) % n };
+            let matches = self.line_matches(re, line);
+
+            let found = match (i, backward) {
+                (0, false) => matches.iter().find(|m| key(m) > from.col),
+                (0, true)  => matches.iter().rev().find(|m| key(m) < from.col),
+                (_, false) => matches.first(),
+                (_, true)  => matches.last(),
+            };
+            if let Some(m) = found    { return Some((line, m.0, m.1)); }
+        }
+        None
+    }
+
+    /*
+     * Moves to the count'th match from `from` and applies the offset. A
+     * character offset is first taken back off the starting point, so
+     * repeating a search doesn't find the same match again.
+     */
+    fn search(&mut self, re : &Regex, backward : bool, offset : SearchOffset, count : u32, from : Pos) {
+        let mut pos = from;
+        let by_end  = matches!(offset, SearchOffset::End(_));
+
+        if let SearchOffset::Start(n) | SearchOffset::End(n) = offset {
+            pos.col = (pos.col as i64 - n as i64).max(0) as usize;
+        }
+
+        let mut found = None;
+        for _ in 0..count {
+            match self.find_match(re, backward, by_end, pos) {
+                Some(m) => {
+                    pos   = Pos{ line : m.0, col : if by_end { m.2.max(m.1 + 1) - 1 } else { m.1 } };
+                    found = Some(m);
+                },
+                None    => break,
+            }
+        }
+
+        let (line, beg, end) = match found {
+            Some(m) => m,
+            None    => { self.failed = true; return; },
+        };
+        let len = |l : usize| self.lines[l].chars().count() as i64;
+        let col = |c : i64, l : usize| c.clamp(0, (len(l) - 1).max(0)) as usize;
+
+        (self.line, self.cursor) = match offset {
+            SearchOffset::Start(n) => (line, col(beg as i64 + n as i64, line)),
+            SearchOffset::End(n)   => (line, col(end.max(beg + 1) as i64 - 1 + n as i64, line)),
+            SearchOffset::Line(n)  => {
+                let l = (line as i64 + n as i64).clamp(0, self.lines.len() as i64 - 1) as usize;
+                (l, 0)
+            },
+        };
+    }
+
+    fn execute_Search(&mut self, op : &Op, _line : usize) {
+        let m = op.get_motion();
+        let (re, backward, offset) = match &m.mot {
+            SingleMotion::Search { re, backward, offset } => (re.clone(), *backward, *offset),
+            _                                              => panic!("internal error: execute_Search() -- missing pattern")
+        };
+
+        let re = match re.or_else(|| self.last_s.as_ref().map(|s| s.re.clone())) {
+            Some(re) => re,
+            None     => { self.failed = true; return; },
+        };
+        self.last_s = Some(LastSearch{ re : re.clone(), backward, offset });
 
-        self.last_f = Some(op.get_motion().clone()); 
+        let from = Pos{ line : self.line, col : self.cursor };
+        self.search(&re, backward, offset, m.repeat, from);
     }
 
-    fn execute_BackFind(&mut self, op : &Op, line : usize) {
-        let c          = op.get_c();
-        let s : String = self.lines[line].chars().take(self.cursor).collect();
+    fn execute_SearchNext(&mut self, op : &Op, _line : usize) {
+        let m       = op.get_motion();
+        let reverse = match m.mot {
+            SingleMotion::SearchNext { reverse } => reverse,
+            _                                    => panic!("internal error: execute_SearchNext() -- missing direction")
+        };
 
-        if let Some(p) = s.rfind(c)    { self.cursor = p; }
-        
-        self.last_f = Some(op.get_motion().clone()); 
+        match self.last_s.clone() {
+            Some(s) => {
+                let from = Pos{ line : self.line, col : self.cursor };
+                self.search(&s.re, s.backward != reverse, s.offset, m.repeat, from);
+            },
+            None    => self.failed = true,
+        }
     }
 
-    fn execute_FindNext(&mut self, _op : &Op, line : usize) {
-        if let Some(mot) = self.last_f {
-            let o = Op::Motion(mot);
-            self.execute_Motion(&o, line);
+    /*
+     * * and # search for the keyword under or after the cursor as a whole
+     * word, or for the non-blank text there when there is no keyword.
+     */
+    fn execute_SearchWord(&mut self, op : &Op, line : usize) {
+        let m        = op.get_motion();
+        let backward = match m.mot {
+            SingleMotion::SearchWord { backward } => backward,
+            _                                     => panic!("internal error: execute_SearchWord() -- missing direction")
+        };
+
+        let chars : Vec<char> = self.lines[line].chars().collect();
+        let class = |c : char| char_class(c, false);
+
+        let kind = match chars[self.cursor.min(chars.len())..].iter().find(|c| class(**c) == 2) {
+            Some(_) => 2,
+            None    => 1,
+        };
+        let ok = |c : char| if kind == 2 { class(c) == 2 } else { class(c) != 0 };
+
+        let mut beg = self.cursor;
+        while beg < chars.len() && !ok(chars[beg])    { beg += 1; }
+        if beg == chars.len() {
+            self.failed = true;
+            return;
+        }
+        if beg == self.cursor {
+            while beg > 0 && ok(chars[beg - 1])    { beg -= 1; }
         }
+        let mut end = beg;
+        while end < chars.len() && ok(chars[end])    { end += 1; }
+
+        let word : String = chars[beg..end].iter().collect();
+        let pat           = match kind {
+            2 => format!(r"\b{}\b", regex::escape(&word)),
+            _ => regex::escape(&word),
+        };
+        let re = Regex::new(&pat).unwrap();
+        self.last_s = Some(LastSearch{ re : re.clone(), backward, offset : SearchOffset::Start(0) });
+
+        /* # starts from the beginning of the word so it doesn't find itself */
+        let from = Pos{ line, col : if backward { beg } else { self.cursor } };
+        self.search(&re, backward, SearchOffset::Start(0), m.repeat, from);
     }
 
-    fn get_motion_fn(&self, mot : &Motion) -> Box<Fn(&mut Worker, &Op, usize)> {
-        let f = match mot.mot {
+    fn get_motion_fn(&self, mot : &Motion) -> fn(&mut Worker, &Op, usize) {
+        match mot.mot {
             SingleMotion::Beg             => Worker::execute_Beg,
             SingleMotion::End             => Worker::execute_End,
+            SingleMotion::FirstNonBlank   => Worker::execute_FirstNonBlank,
+            SingleMotion::LastNonBlank    => Worker::execute_LastNonBlank,
+            SingleMotion::Column   { .. } => Worker::execute_Column,
+            SingleMotion::Match           => Worker::execute_Match,
+            SingleMotion::Percent  { .. } => Worker::execute_Percent,
             SingleMotion::Left            => Worker::execute_Left,
             SingleMotion::Right           => Worker::execute_Right,
-            SingleMotion::Word            => Worker::execute_Word,
-            SingleMotion::Back            => Worker::execute_Back,
-            SingleMotion::Till     { .. } => Worker::execute_Till,
-            SingleMotion::BackTill { .. } => Worker::execute_BackTill,
-            SingleMotion::Find     { .. } => Worker::execute_Find,
-            SingleMotion::BackFind { .. } => Worker::execute_BackFind,
-            SingleMotion::FindNext        => Worker::execute_FindNext,
-        };
-        Box::new(move|worker, op, l| {
-            let mut bad = false;
-            let save = worker.cursor;
-            for _ in 0..op.get_repeat() {
-                let c = worker.cursor;
-                f(worker, op, l);
-                if c == worker.cursor { bad = true; } 
-            }
-            if bad { worker.cursor = save; }
-        })
+            SingleMotion::Word
+          | SingleMotion::BigWord         => Worker::execute_Word,
+            SingleMotion::Back
+          | SingleMotion::BigBack         => Worker::execute_Back,
+            SingleMotion::WordEnd
+          | SingleMotion::BigWordEnd      => Worker::execute_WordEnd,
+            SingleMotion::WordEndBack
+          | SingleMotion::BigWordEndBack  => Worker::execute_WordEndBack,
+            SingleMotion::Till     { .. }
+          | SingleMotion::BackTill { .. }
+          | SingleMotion::Find     { .. }
+          | SingleMotion::BackFind { .. }
+          | SingleMotion::FindNext { .. } => Worker::execute_Find,
+            SingleMotion::Down            => Worker::execute_Down,
+            SingleMotion::Up              => Worker::execute_Up,
+            SingleMotion::GotoLine { .. } => Worker::execute_GotoLine,
+            SingleMotion::Mark     { .. }
+          | SingleMotion::MarkLine { .. } => Worker::execute_GotoMark,
+            SingleMotion::Search   { .. } => Worker::execute_Search,
+            SingleMotion::SearchNext { .. } => Worker::execute_SearchNext,
+            SingleMotion::SearchWord { .. } => Worker::execute_SearchWord,
+        }
     }
 
     fn execute_Motion(&mut self, op : &Op, line : usize) {
@@ -198,189 +798,1629 @@ impl Worker {
             Op::Motion(mot) => mot,
             _               => panic!("internal error: execute_Motion() on non-motion")
         };
+
+        /*
+         * Every motion handles its own count and says when it fails, which
+         * leaves the cursor where it was.
+         */
+        let save = (self.line, self.cursor);
+        self.failed = false;
         self.get_motion_fn(mot)(self, op, line);
+        if self.failed    { (self.line, self.cursor) = save; }
+    }
+
+    /*
+     * Whether a search motion ends on the match's last character or makes
+     * the range linewise depends on the offset of the last search.
+     */
+    fn search_offset(&self, m : &Motion) -> Option<SearchOffset> {
+        match m.mot {
+              SingleMotion::Search     { .. }
+            | SingleMotion::SearchNext { .. } => self.last_s.as_ref().map(|s| s.offset),
+            _                                 => None
+        }
     }
 
     fn op_motion_is_inclusive(&self, op : &Op) -> bool {
         match op {
             Op::Motion(m) => {
                 match m.mot {
-                      SingleMotion::Till { .. }
-                    | SingleMotion::Find { .. }         => true,
-                    SingleMotion::FindNext => match self.last_f {
-                        Some(m) => match m.mot {
-                              SingleMotion::Till { .. }
-                            | SingleMotion::Find { .. } => true,
-                            _                           => false
-                        },
-                        _                               => panic!("internal error: op_motion_is_inclusive() -- missing self.last_f")
-                    },
-                    _                                   => false
+                      SingleMotion::LastNonBlank
+                    | SingleMotion::Match
+                    | SingleMotion::WordEnd
+                    | SingleMotion::BigWordEnd
+                    | SingleMotion::WordEndBack
+                    | SingleMotion::BigWordEndBack      => true,
+                    /* f, t, and ; or , going forward take the character they land on */
+                      SingleMotion::Till     { .. }
+                    | SingleMotion::BackTill { .. }
+                    | SingleMotion::Find     { .. }
+                    | SingleMotion::BackFind { .. }
+                    | SingleMotion::FindNext { .. }     => self.char_search(m).is_some_and(|(_, forward, _)| forward),
+                    _ => matches!(self.search_offset(m), Some(SearchOffset::End(_)))
                 }
             },
             _ => panic!("internal error: op_motion_is_inclusive() on non-motion")
         }
     }
 
-    fn execute_Insert(&mut self, op : &Op, line : usize) {
-        let s = op.get_s();
+    fn motion_is_linewise(&self, m : &Motion) -> bool {
+        m.is_linewise() || matches!(self.search_offset(m), Some(SearchOffset::Line(_)))
+    }
 
-        if s.len() > 0 {
-            let before : String = self.lines[line].chars().take(self.cursor).collect();
-            let after  : String = self.lines[line].chars().skip(self.cursor).collect();
+    /*
+     * Types `s` in before character `pos` the way insert mode takes keys,
+     * with 'backspace' set to "indent,eol,start":
+     *   <CR> or \n  splits the line
+     *   <BS>        deletes the character before the cursor
+     *   <C-w>       deletes the word before the cursor
+     *   <C-u>       deletes everything before the cursor on the line
+     *   <C-r>{reg}  inserts the text of a register
+     *   <C-o>       leaves insert mode for one command; see execute_Resume()
+     * The cursor is left where leaving insert mode puts it.
+     */
+    fn insert_text(&mut self, line : usize, pos : usize, s : &str) {
+        let mut at     = Pos{ line, col : pos };
+        let mut start  = at;
+        let mut keys   = s.chars();
+        let mut ctrl_o = false;
 
-            self.lines[line] = before + &s + &after;
+        let typed      = !s.starts_with('\x0f');
 
-            self.cursor += s.len() - 1;
+        while let Some(k) = keys.next() {
+            match k {
+                '\x08' | '\x17' | '\x15' => self.erase(&mut at, &mut start, k),
+                '\x12'                   => {
+                    for c in self.register_keys(&mut keys).chars()    { self.type_char(&mut at, c); }
+                },
+                '\x0f'                   => { ctrl_o = true; break; },
+                c                        => self.type_char(&mut at, c),
+            }
         }
+        if !s.is_empty()    { self.empty = false; }
+        self.leave_insert(at, ctrl_o, typed);
     }
 
-    fn execute_Append(&mut self, op : &Op, line : usize) {
-        let s = op.get_s();
+    /* <C-r><C-r>, <C-r><C-o> and <C-r><C-p> insert the text as it is too */
+    fn register_keys(&self, keys : &mut std::str::Chars) -> String {
+        let mut r = keys.next();
+        if matches!(r, Some('\x12' | '\x0f' | '\x10'))    { r = keys.next(); }
+        r.and_then(|r| self.regs.get(r)).map(|r| r.text.clone()).unwrap_or_default()
+    }
+
+    fn type_char(&mut self, at : &mut Pos, c : char) {
+        let mut chars : Vec<char> = self.lines[at.line].chars().collect();
+        if c == '\r' || c == '\n' {
+            let rest = chars.split_off(at.col);
+            self.splice_lines(at.line..at.line + 1, vec![chars.into_iter().collect(), rest.into_iter().collect()]);
+            *at = Pos{ line : at.line + 1, col : 0 };
+        } else {
+            chars.insert(at.col, c);
+            self.lines[at.line] = chars.into_iter().collect();
+            at.col += 1;
+        }
+    }
 
-        if s.len() > 0 {
-            let before : String = self.lines[line].chars().take(self.cursor + 1).collect();
-            let after  : String = self.lines[line].chars().skip(self.cursor + 1).collect();
+    /*
+     * <BS>, <C-w> or <C-u> typed at `at`. At the start of a line each of
+     * them joins it to the line above. <C-w> and <C-u> stop at `start`,
+     * where the insert began, the first time they get there.
+     */
+    fn erase(&mut self, at : &mut Pos, start : &mut Pos, key : char) {
+        if at.col == 0 {
+            if at.line == 0    { return; }
+            let above = self.lines[at.line - 1].clone();
+            let col   = above.chars().count();
+            if start.line == at.line    { *start = Pos{ line : at.line - 1, col }; }
 
-            self.lines[line] = before + &s + &after;
+            self.join_marks(at.line, at.line - 1, col, 0);
+            let joined = above + &self.lines[at.line];
+            self.splice_lines(at.line - 1..at.line + 1, vec![joined]);
+            *at = Pos{ line : at.line - 1, col };
+            return;
+        }
 
-            self.cursor += s.len();
+        /* <C-w> goes back over blanks, then over characters of one class */
+        let mut chars : Vec<char> = self.lines[at.line].chars().collect();
+        let mut class             = None;
+        loop {
+            if key == '\x17' {
+                let c = char_class(chars[at.col - 1], false);
+                match class {
+                    None if c != 0              => class = Some(c),
+                    Some(k) if c != k           => break,
+                    _                           => {},
+                }
+            }
+            chars.remove(at.col - 1);
+            at.col -= 1;
+            if key == '\x08' || at.col == 0 || *at == *start    { break; }
         }
+        if at.line == start.line && at.col < start.col    { start.col = at.col; }
+        self.lines[at.line] = chars.into_iter().collect();
     }
 
-    fn execute_LineAppend(&mut self, op : &Op, line : usize) {
-        let s = op.get_s();
+    /*
+     * Leaving insert mode puts the cursor on the character before it.
+     * Leaving it for one command with <C-o> doesn't, unless the cursor is
+     * past the end of the line, and the column vertical motions aim for
+     * stays where the cursor was while typing.
+     */
+    fn leave_insert(&mut self, at : Pos, ctrl_o : bool, typed : bool) {
+        self.last_insert = Some(at);
+
+        let len     = self.lines[at.line].chars().count();
+        self.line   = at.line;
+        self.cursor = if ctrl_o && at.col < len { at.col } else { at.col.saturating_sub(1) };
+        self.at_eol = if ctrl_o && at.col >= len { Some(at.line) } else { None };
+
+        if ctrl_o {
+            if typed    { self.want = at.col; }
+            self.want_set = true;
+        }
+    }
 
-        if s.len() > 0 {
-            let before = self.lines[line].clone();
+    /*
+     * Goes back to inserting, or replacing, after the command run with
+     * <C-o>. Like Vim, it goes on after the last character of the line if
+     * <C-o> was typed at the end of the line and the command stayed on
+     * it, or if the command went to the end of a line.
+     */
+    fn execute_Resume(&mut self, op : &Op, line : usize) {
+        let (s, replace) = match op {
+            Op::Resume{ s, replace } => (s.clone(), *replace),
+            _                        => panic!("internal error: execute_Resume() -- missing text")
+        };
 
-            self.lines[line] = before + &s;
+        let len     = self.lines[line].chars().count();
+        let at_eol  = self.at_eol.take() == Some(line) || self.want > self.cursor;
+        let mut pos = self.cursor.min(len);
+        if at_eol && pos + 1 == len    { pos = len; }
 
-            self.cursor = if self.lines[line].len() > 0 { self.lines[line].len() - 1 } else { 0 };
+        self.op_start = Some(Pos{ line, col : pos });
+        if replace {
+            self.cursor = pos;
+            self.execute_Overwrite(&Op::Overwrite{ s, n : 1 }, line);
+        } else {
+            self.insert_text(line, pos, &s);
         }
     }
 
-    fn execute_Delete(&mut self, op : &Op, line : usize) {
-        let old_cursor = self.cursor;
-        let mot        = Op::Motion(match op {
-            Op::Delete{ motion } => motion.clone(),
-            _                    => panic!("internal error: execute_Delete() -- missing motion")
-        });
-      
-        let inclusive = self.op_motion_is_inclusive(&mot) as usize;
+    fn execute_Insert(&mut self, op : &Op, line : usize) {
+        let s = op.get_s().repeat(op.get_n());
+        self.insert_text(line, self.cursor, &s);
+    }
 
-        self.execute_Motion(&mot, line);
+    fn execute_Append(&mut self, op : &Op, line : usize) {
+        let s   = op.get_s().repeat(op.get_n());
+        let pos = (self.cursor + 1).min(self.lines[line].chars().count());
 
-        if self.cursor != old_cursor {
-            let (beg, end)      = if old_cursor < self.cursor { (old_cursor, self.cursor) }
-                                  else                        { (self.cursor, old_cursor) };
-            let first  : String = self.lines[line].chars().take(beg).collect();
-            let second : String = self.lines[line].chars().skip(end + inclusive).collect();
-            self.lines[line]    = first + &second;
+        self.op_start = Some(Pos{ line, col : pos });
+        self.insert_text(line, pos, &s);
+    }
 
-            self.cursor = beg;
-        }
+    fn execute_LineAppend(&mut self, op : &Op, line : usize) {
+        let s   = op.get_s().repeat(op.get_n());
+        let pos = self.lines[line].chars().count();
+
+        self.op_start = Some(Pos{ line, col : pos });
+        self.insert_text(line, pos, &s);
     }
 
-    fn execute_DeleteToEnd(&mut self, _op : &Op, line : usize) {
-        self.lines[line] = self.lines[line].chars().take(self.cursor).collect();
+    /* I inserts after the indent, or at the end of a line of blanks */
+    fn execute_LineInsert(&mut self, op : &Op, line : usize) {
+        let s   = op.get_s().repeat(op.get_n());
+        let pos = self.lines[line].chars().position(|c| !c.is_whitespace())
+                      .unwrap_or(self.lines[line].chars().count());
+
+        self.op_start = Some(Pos{ line, col : pos });
+        self.insert_text(line, pos, &s);
     }
 
-    fn execute_Yank(&mut self, op : &Op, line : usize) {
-        let old_cursor = self.cursor;
-        let mot        = Op::Motion(match op {
-            Op::Yank{ motion } => motion.clone(),
-            _                  => panic!("internal error: execute_Yank() -- missing motion")
+    fn execute_StartInsert(&mut self, op : &Op, line : usize) {
+        let s = op.get_s().repeat(op.get_n());
+
+        self.op_start = Some(Pos{ line, col : 0 });
+        self.insert_text(line, 0, &s);
+    }
+
+    /*
+     * gi inserts where insert mode was left last, which moves with the
+     * lines like a mark; before any insert it inserts at the cursor.
+     */
+    fn execute_LastInsert(&mut self, op : &Op, line : usize) {
+        let s                = op.get_s().repeat(op.get_n());
+        let Pos{ line, col } = match self.last_insert {
+            Some(p) if p.line < self.lines.len() => p,
+            _                                    => Pos{ line, col : self.cursor },
+        };
+        let pos = col.min(self.lines[line].chars().count());
+
+        self.op_start = Some(Pos{ line, col : pos });
+        self.insert_text(line, pos, &s);
+    }
+
+    /*
+     * Returns the range covered by an operator target, or None when the
+     * motion fails or no matching object is found. Where the operator
+     * starts is kept in `op_start` for undo.
+     */
+    fn get_range(&mut self, target : &Target, line : usize) -> Option<Range> {
+        self.op_start = Some(Pos{ line, col : self.cursor });
+        let len   = self.lines[line].chars().count();
+        let chars = |beg : usize, end : usize| Some(Range {
+            beg      : Pos{ line, col : beg },
+            end      : Pos{ line, col : end },
+            linewise : false
         });
-      
-        let inclusive = self.op_motion_is_inclusive(&mot) as usize;
 
-        self.execute_Motion(&mot, line);
+        match target {
+            /*
+             * 'l' and '$' may cover the last character even though the
+             * cursor can't move past it. A count takes '$' down to the end
+             * of a later line. On an empty line they cover nothing, which
+             * c still inserts into.
+             */
+            Target::Motion(Motion{ mot : SingleMotion::Right, repeat }) => {
+                if self.cursor >= len && len > 0    { return None; }
+                chars(self.cursor, (self.cursor + *repeat as usize).min(len))
+            },
+            Target::Motion(Motion{ mot : SingleMotion::End, repeat }) => {
+                let end = self.count_down(line, *repeat)?;
+                if *repeat == 1 && self.cursor >= len && len > 0    { return None; }
+                Some(Range {
+                    beg      : Pos{ line, col : self.cursor },
+                    end      : Pos{ line : end, col : self.lines[end].chars().count() },
+                    linewise : false
+                })
+            },
+            Target::Motion(motion) => {
+                let old = Pos{ line, col : self.cursor };
+                let mot = Op::Motion(motion.clone());
+
+                self.pending = true;
+                self.execute_Motion(&mot, line);
+                self.pending = false;
+
+                if self.failed    { return None; }
+
+                let inclusive      = self.op_motion_is_inclusive(&mot);
+                let new            = Pos{ line : self.line, col : self.cursor };
+                let (beg, mut end) = if old <= new { (old, new) } else { (new, old) };
+                self.op_start      = Some(beg);
+
+                if self.motion_is_linewise(motion) {
+                    return Some(Range{ beg, end, linewise : true });
+                }
+                if inclusive {
+                    end.col += 1;
+                } else if beg == end {
+                    return None;
+                }
 
-        if self.cursor != old_cursor {
-            let (beg, end)      = if old_cursor < self.cursor { (old_cursor, self.cursor) }
-                                  else                        { (self.cursor, old_cursor) };
-            self.pb = self.lines[line].chars().skip(beg).take(end - beg + inclusive).collect();
+                /*
+                 * An exclusive motion ending in the first column stops at the
+                 * end of the line before instead, and becomes linewise if it
+                 * started at or before the first non-blank.
+                 */
+                if !inclusive && end.line > beg.line && end.col == 0 {
+                    end.line -= 1;
+                    end.col   = self.lines[end.line].chars().count();
+                    if beg.col <= first_non_blank(&self.lines[beg.line]) {
+                        return Some(Range{ beg, end, linewise : true });
+                    }
+                }
+                Some(Range{ beg, end, linewise : false })
+            },
+            Target::Line(n) => {
+                let last = self.lines.len() - 1;
+                if *n > 1 && line == last    { return None; }
+                if *n == 1 {
+                    let col       = self.cursor.min(first_non_blank(&self.lines[line]));
+                    self.op_start = Some(Pos{ line, col });
+                }
+                let end = (line + *n as usize - 1).min(last);
+                Some(Range{ beg : Pos{ line, col : 0 }, end : Pos{ line : end, col : 0 }, linewise : true })
+            },
+            Target::Object(obj) => {
+                /* a word object on an empty line is empty too; a quote or block fails */
+                let text : Vec<char> = self.lines[line].chars().collect();
+                if text.is_empty() {
+                    return match obj.obj {
+                        TextObject::Word { .. } | TextObject::BigWord { .. } => chars(0, 0),
+                        _                                                    => None,
+                    };
+                }
+                let cursor = self.cursor.min(text.len() - 1);
 
-            self.cursor = beg;
+                let (beg, end) = match obj.obj {
+                    TextObject::Word    { inner }              => word_object(&text, cursor, obj.repeat, inner, false),
+                    TextObject::BigWord { inner }              => word_object(&text, cursor, obj.repeat, inner, true),
+                    TextObject::Quote   { c, inner }           => quote_object(&text, cursor, c, obj.repeat, inner),
+                    TextObject::Block   { open, close, inner } => block_object(&text, cursor, open, close, obj.repeat, inner),
+                }?;
+                self.op_start = Some(Pos{ line, col : beg });
+                chars(beg, end)
+            }
         }
     }
 
-    fn execute_Put(&mut self, _op : &Op, line : usize) {
-        if self.pb.len() > 0 {
-            let before : String = self.lines[line].chars().take(self.cursor + 1).collect();
-            let after  : String = self.lines[line].chars().skip(self.cursor + 1).collect();
+    fn range_text(&self, r : &Range) -> String {
+        if r.linewise {
+            return self.lines[r.beg.line..=r.end.line].iter().map(|l| l.clone() + "\n").collect();
+        }
 
-            self.lines[line] = before + &self.pb + &after;
-            self.cursor += self.pb.len();
+        let mut text = String::new();
+        for l in r.beg.line..=r.end.line {
+            let beg = if l == r.beg.line { r.beg.col } else { 0 };
+            let end = if l == r.end.line { r.end.col } else { usize::MAX };
+            if l != r.beg.line    { text.push('\n'); }
+            text.extend(self.lines[l].chars().skip(beg).take(end.saturating_sub(beg)));
         }
+        text
     }
 
-    fn execute_Repeat(&mut self, _op : &Op, line : usize) {
-        if let Some(o) = self.last_o.clone() {
-            let execute_fn = self.get_execute_fn(&o);
-            execute_fn(self, &o, line);
-        }
+    /*
+     * Deleted text goes to the registers; only charwise text from a single
+     * line counts as a small delete.
+     */
+    fn save_deleted(&mut self, r : &Range, reg : Option<char>, to_one : bool) {
+        let text  = self.range_text(r);
+        let small = !r.linewise && r.beg.line == r.end.line;
+        self.regs.delete(reg, Register{ text, linewise : r.linewise }, small, to_one);
     }
 
-    fn get_execute_fn(&self, op : &Op) -> fn(&mut Worker, &Op, usize) {
-        match op {
-            Op::Motion(_)         => Worker::execute_Motion,
-            Op::Insert     { .. } => Worker::execute_Insert,
-            Op::Append     { .. } => Worker::execute_Append,
-            Op::LineAppend { .. } => Worker::execute_LineAppend,
-            Op::Delete     { .. } => Worker::execute_Delete,
-            Op::DeleteToEnd       => Worker::execute_DeleteToEnd,
-            Op::Yank       { .. } => Worker::execute_Yank,
-            Op::Put               => Worker::execute_Put,
-            Op::Repeat            => Worker::execute_Repeat,
+    /*
+     * Removes the text in a range, saving it in the registers the way a
+     * delete does. Deleting every line leaves a single empty line that is
+     * not written out unless something is put back into the buffer.
+     */
+    fn delete_range(&mut self, r : &Range, reg : Option<char>, to_one : bool) {
+        self.save_deleted(r, reg, to_one);
+
+        if r.linewise {
+            self.splice_lines(r.beg.line..r.end.line + 1, Vec::new());
+            if self.lines.is_empty() {
+                self.lines.push(String::new());
+                self.empty = true;
+            }
+            self.line   = r.beg.line.min(self.lines.len() - 1);
+            self.cursor = first_non_blank(&self.lines[self.line]);
+        } else {
+            let first  : String = self.lines[r.beg.line].chars().take(r.beg.col).collect();
+            let second : String = self.lines[r.end.line].chars().skip(r.end.col).collect();
+            if r.end.line > r.beg.line {
+                self.join_marks(r.end.line, r.beg.line, r.beg.col, 0);
+            }
+            self.splice_lines(r.beg.line..r.end.line + 1, vec![first + &second]);
+
+            self.line   = r.beg.line;
+            self.cursor = r.beg.col;
+            self.clamp_cursor(self.line);
         }
     }
 
-    fn execute(&mut self, ops : &Vec<Op>) {
-        for i in 0..self.lines.len() {
-            self.cursor = 0;
-            self.pb = String::new();
-            for op in ops {
-                let execute_fn = self.get_execute_fn(&op);
-                execute_fn(self, op, i);
-                self.last_o = match op {
-                    Op::Motion(_) => None,
-                    Op::Repeat    => self.last_o.clone(),
-                    _             => Some(op.clone())
-                };
+    /*
+     * Replaces the lines in `range` with `new`, moving marks as Vim does:
+     * marks below the range shift with their lines, and marks on lines
+     * that are gone are deleted. Where the last insert stopped moves the
+     * same way.
+     */
+    fn splice_lines(&mut self, range : std::ops::Range<usize>, new : Vec<String>) {
+        let (beg, end, n) = (range.start, range.end, new.len());
+        for mark in self.marks.iter_mut().chain(std::iter::once(&mut self.last_insert)) {
+            match mark {
+                Some(p) if p.line >= end     => p.line = p.line - (end - beg) + n,
+                Some(p) if p.line >= beg + n => *mark = None,
+                _                            => {},
             }
         }
+        self.lines.splice(range, new);
     }
-}
-
-struct Manager {
-    count     : usize,
-    workers   : Vec<Worker>,
-}
 
-impl Manager {
-    fn new() -> Manager {
-        Manager {
-            count     : 0,
-            workers   : Vec::new(),
+    /*
+     * Moves the marks on line `from` to line `to` where it is joined on,
+     * its text starting at column `start` with `removed` leading
+     * characters left out.
+     */
+    fn join_marks(&mut self, from : usize, to : usize, start : usize, removed : usize) {
+        for p in self.marks.iter_mut().flatten().chain(self.last_insert.as_mut()) {
+            if p.line == from {
+                p.line = to;
+                p.col  = if p.col < removed { start } else { p.col - removed + start };
+            }
         }
     }
 
-    fn add_worker(&mut self, lines : Vec<String>) {
-        let worker = Worker {
-            pos    : self.count,
-            cursor : 0,
-            pb     : String::new(),
-            last_f : None,
-            last_o : None,
-            lines  : lines
+    fn clamp_cursor(&mut self, line : usize) {
+        let len = self.lines[line].chars().count();
+        if self.cursor >= len    { self.cursor = if len > 0 { len - 1 } else { 0 }; }
+    }
+
+    fn execute_Delete(&mut self, op : &Op, line : usize) {
+        let (target, reg) = match op {
+            Op::Delete{ target, reg } => (target.clone(), *reg),
+            _                         => panic!("internal error: execute_Delete() -- missing target")
+        };
+
+        if let Some(mut r) = self.get_range(&target, line) {
+            /* like Vim, deleting nothing on an empty line leaves the registers alone */
+            if !r.linewise && r.beg == r.end    { return; }
+
+            /*
+             * Like Vim, deleting characters over several lines from within
+             * the indent up to the end of a line deletes the lines.
+             */
+            if !r.linewise && r.beg.line < r.end.line
+            && first_non_blank(&self.lines[r.beg.line]) >= r.beg.col
+            && self.lines[r.end.line].chars().skip(r.end.col).all(|c| c.is_whitespace()) {
+                r.linewise = true;
+            }
+            self.delete_range(&r, reg, target.uses_reg_one());
+        }
+    }
+
+    fn execute_Change(&mut self, op : &Op, line : usize) {
+        let (target, s, reg) = match op {
+            Op::Change{ target, s, reg } => (target.clone(), s.clone(), *reg),
+            _                            => panic!("internal error: execute_Change() -- missing target")
+        };
+
+        let on_blank = self.lines[line].chars().nth(self.cursor).is_none_or(|c| c.is_whitespace());
+
+        /*
+         * Vim special case: on a non-blank, "cw" and "cW" don't take the
+         * whitespace after the word, so they act like "ce" and "cE", except
+         * that a cursor on the end of a word stays there.
+         */
+        let range = match target {
+            Target::Motion(Motion{ mot : ref mot @ (SingleMotion::Word | SingleMotion::BigWord), repeat }) if !on_blank => {
+                let beg    = Pos{ line, col : self.cursor };
+                let big    = matches!(mot, SingleMotion::BigWord);
+                let mut wc = WordCursor::new(&self.lines, beg, big);
+                wc.end_word(repeat, true, false);
+
+                let end = Pos{ line : wc.pos.line, col : wc.pos.col.min(wc.chars.len().saturating_sub(1)) + 1 };
+                Some(Range{ beg, end, linewise : false })
+            },
+            /* with no word to go to, "cw" on an empty last line changes nothing */
+            Target::Motion(Motion{ mot : SingleMotion::Word | SingleMotion::BigWord, .. })
+                if self.lines[line].is_empty() && line + 1 == self.lines.len() => {
+                let at = Pos{ line, col : 0 };
+                Some(Range{ beg : at, end : at, linewise : false })
+            },
+            _ => self.get_range(&target, line)
+        };
+
+        /*
+         * Changing lines leaves one empty line in their place to insert
+         * into. As in Vim, "cl" and "s" on an empty line yank nothing,
+         * since 'l' is exclusive, while "C" and "ciw" yank the empty text.
+         */
+        if let Some(r) = range {
+            let exclusive = matches!(target, Target::Motion(Motion{ mot : SingleMotion::Right, .. }));
+            if r.linewise {
+                self.save_deleted(&r, reg, target.uses_reg_one());
+                self.splice_lines(r.beg.line..r.end.line + 1, vec![String::new()]);
+            } else if r.beg != r.end || !exclusive {
+                self.delete_range(&r, reg, target.uses_reg_one());
+            }
+            self.insert_text(r.beg.line, r.beg.col, &s);
+        }
+    }
+
+    /* D is d$, count and all */
+    fn execute_DeleteToEnd(&mut self, op : &Op, line : usize) {
+        let (reg, n) = match op {
+            Op::DeleteToEnd{ reg, n } => (*reg, *n),
+            _                         => panic!("internal error: execute_DeleteToEnd() -- missing register")
+        };
+
+        let target = Target::Motion(Motion{ mot : SingleMotion::End, repeat : n });
+        self.execute_Delete(&Op::Delete{ target, reg }, line);
+    }
+
+    fn execute_Yank(&mut self, op : &Op, line : usize) {
+        let (target, reg) = match op {
+            Op::Yank{ target, reg } => (target.clone(), *reg),
+            _                       => panic!("internal error: execute_Yank() -- missing target")
+        };
+
+        let col = self.cursor;
+
+        if let Some(r) = self.get_range(&target, line) {
+            let text = self.range_text(&r);
+            self.regs.yank(reg, Register{ text, linewise : r.linewise });
+
+            /* yanking lines keeps the column */
+            self.line = r.beg.line;
+            if r.linewise {
+                self.cursor = col;
+                self.clamp_cursor(self.line);
+            } else {
+                self.cursor = r.beg.col;
+            }
+        }
+    }
+
+    /*
+     * A count puts the register's text that many times over. gp and gP leave
+     * the cursor just after the text: on the line after put lines, or after
+     * the last character put.
+     */
+    fn execute_Put(&mut self, op : &Op, line : usize) {
+        let (reg, n, after, past) = match op {
+            Op::Put      { reg, n, past } => (*reg, *n as usize, true, *past),
+            Op::PutBefore{ reg, n, past } => (*reg, *n as usize, false, *past),
+            _                             => panic!("internal error: execute_Put() -- missing register")
+        };
+
+        let r = match self.regs.get(reg.unwrap_or('"')) {
+            Some(r) if !r.text.is_empty() => Register{ text : r.text.repeat(n), linewise : r.linewise },
+            _                             => return
         };
+
+        if r.linewise {
+            let text                    = r.text.strip_suffix('\n').unwrap_or(&r.text);
+            let new_lines : Vec<String> = text.split('\n').map(String::from).collect();
+            let at                      = if after { line + 1 } else { line };
+            let next                    = at + new_lines.len();
+            self.splice_lines(at..at, new_lines);
+
+            self.empty = false;
+            if past {
+                self.line   = next.min(self.lines.len() - 1);
+                self.cursor = 0;
+            } else {
+                self.line   = at;
+                self.cursor = first_non_blank(&self.lines[at]);
+            }
+        } else if r.text.contains('\n') {
+            /* text spanning lines splits the line, leaving the cursor at its start */
+            let len = self.lines[line].chars().count();
+            let pos = if after { (self.cursor + 1).min(len) } else { self.cursor.min(len) };
+
+            let first  : String = self.lines[line].chars().take(pos).collect();
+            let second : String = self.lines[line].chars().skip(pos).collect();
+            let mut new_lines : Vec<String> = r.text.split('\n').map(String::from).collect();
+            let last                        = line + new_lines.len() - 1;
+            let end                         = new_lines[new_lines.len() - 1].chars().count();
+            new_lines[0].insert_str(0, &first);
+            new_lines.last_mut().unwrap().push_str(&second);
+            self.splice_lines(line..line + 1, new_lines);
+
+            self.empty = false;
+            if past {
+                self.line   = last;
+                self.cursor = end;
+                self.clamp_cursor(last);
+            } else {
+                self.line   = line;
+                self.cursor = pos;
+            }
+        } else {
+            let chars : Vec<char> = self.lines[line].chars().collect();
+            let pos               = if after { (self.cursor + 1).min(chars.len()) } else { self.cursor.min(chars.len()) };
+            let first  : String   = chars[..pos].iter().collect();
+            let second : String   = chars[pos..].iter().collect();
+
+            self.lines[line] = first + &r.text + &second;
+            self.empty       = false;
+            self.cursor      = pos + r.text.chars().count() - usize::from(!past);
+            self.clamp_cursor(line);
+        }
+    }
+
+    fn execute_Replace(&mut self, op : &Op, line : usize) {
+        let (c, n) = match op {
+            Op::Replace{ c, n } => (*c, *n as usize),
+            _                   => panic!("internal error: execute_Replace() -- missing character")
+        };
+
+        let mut chars : Vec<char> = self.lines[line].chars().collect();
+
+        /* r<CR> replaces the characters with a single line break */
+        if (c == '\r' || c == '\n') && self.cursor + n <= chars.len() {
+            let before : String = chars[..self.cursor].iter().collect();
+            let after  : String = chars[self.cursor + n..].iter().collect();
+            self.splice_lines(line..line + 1, vec![before, after]);
+
+            self.line   = line + 1;
+            self.cursor = 0;
+        } else if self.cursor + n <= chars.len() {
+            for ch in &mut chars[self.cursor..self.cursor + n]    { *ch = c; }
+            self.lines[line] = chars.into_iter().collect();
+
+            self.cursor += n - 1;
+        }
+    }
+
+    /*
+     * Each character of the text replaces the one under the cursor, so
+     * columns after the overwritten stretch don't shift; past the end of
+     * the line the text is appended. A line break replaces nothing: it
+     * splits the line and overwriting goes on at the start of the next.
+     * The keys insert_text() knows work here too, with <BS>, <C-w> and
+     * <C-u> putting back what was replaced.
+     */
+    fn execute_Overwrite(&mut self, op : &Op, line : usize) {
+        let s = match op {
+            Op::Overwrite{ s, n } => s.repeat(*n as usize),
+            _                     => panic!("internal error: execute_Overwrite() -- missing text")
+        };
+
+        let mut at       = Pos{ line, col : self.cursor.min(self.lines[line].chars().count()) };
+        let mut replaced = Vec::new();
+        let mut keys     = s.chars();
+        let mut ctrl_o   = false;
+
+        while let Some(k) = keys.next() {
+            match k {
+                '\x08' | '\x17' | '\x15' => self.unreplace(&mut at, &mut replaced, k),
+                '\x12'                   => {
+                    for c in self.register_keys(&mut keys).chars()    { self.replace_char(&mut at, &mut replaced, c); }
+                },
+                '\x0f'                   => { ctrl_o = true; break; },
+                c                        => self.replace_char(&mut at, &mut replaced, c),
+            }
+        }
+
+        self.leave_insert(at, ctrl_o, !s.starts_with('\x0f'));
+    }
+
+    fn replace_char(&mut self, at : &mut Pos, replaced : &mut Vec<Replaced>, c : char) {
+        if c == '\r' || c == '\n' {
+            self.type_char(at, c);
+            replaced.push(Replaced::Break);
+            return;
+        }
+
+        let mut chars : Vec<char> = self.lines[at.line].chars().collect();
+        if at.col < chars.len() {
+            replaced.push(Replaced::Char(chars[at.col]));
+            chars[at.col] = c;
+        } else {
+            replaced.push(Replaced::Past);
+            chars.push(c);
+        }
+        self.lines[at.line] = chars.into_iter().collect();
+        at.col += 1;
+    }
+
+    /*
+     * <BS>, <C-w> or <C-u> typed in Replace mode, as erase() does them but
+     * putting back the characters that were replaced. Once they get back
+     * to where replacing began they stop, and from there on they only move
+     * the cursor, going to the end of the line above from the start of a
+     * line; only a line break typed while replacing is joined again.
+     */
+    fn unreplace(&mut self, at : &mut Pos, replaced : &mut Vec<Replaced>, key : char) {
+        if at.col == 0 {
+            if at.line == 0    { return; }
+            let col = self.lines[at.line - 1].chars().count();
+            if let Some(Replaced::Break) = replaced.last() {
+                replaced.pop();
+                self.join_marks(at.line, at.line - 1, col, 0);
+                let joined = self.lines[at.line - 1].clone() + &self.lines[at.line];
+                self.splice_lines(at.line - 1..at.line + 1, vec![joined]);
+            }
+            *at = Pos{ line : at.line - 1, col };
+            return;
+        }
+
+        let mut chars : Vec<char> = self.lines[at.line].chars().collect();
+        let mut class             = None;
+        let replacing             = !replaced.is_empty();
+        loop {
+            if key == '\x17' {
+                let c = char_class(chars[at.col - 1], false);
+                match class {
+                    None if c != 0              => class = Some(c),
+                    Some(k) if c != k           => break,
+                    _                           => {},
+                }
+            }
+            match replaced.last() {
+                Some(&Replaced::Char(c)) => { chars[at.col - 1] = c; replaced.pop(); },
+                Some(Replaced::Past)     => { chars.remove(at.col - 1); replaced.pop(); },
+                _                        => {},
+            }
+            at.col -= 1;
+            if key == '\x08' || at.col == 0 || (replacing && replaced.is_empty())    { break; }
+        }
+        self.lines[at.line] = chars.into_iter().collect();
+    }
+
+    fn execute_ToggleCase(&mut self, op : &Op, line : usize) {
+        let n = match op {
+            Op::ToggleCase{ n } => *n as usize,
+            _                   => panic!("internal error: execute_ToggleCase() -- missing count")
+        };
+
+        let chars : Vec<char> = self.lines[line].chars().collect();
+
+        if self.cursor < chars.len() {
+            let end              = (self.cursor + n).min(chars.len());
+            let first  : String  = chars[..self.cursor].iter().collect();
+            let middle : String  = change_case(&chars[self.cursor..end], CaseOp::Toggle);
+            let second : String  = chars[end..].iter().collect();
+
+            self.cursor     += middle.chars().count();
+            self.lines[line] = first + &middle + &second;
+            self.clamp_cursor(line);
+        }
+    }
+
+    /*
+     * Ctrl-A and Ctrl-X add to the number at or after the cursor, or to
+     * the letter there with 'alpha' in nrformats. The number keeps its
+     * width with leading zeros, and hex digits keep their case; a decimal
+     * number going below zero gains a '-'. The cursor ends up on its last
+     * character.
+     */
+    fn execute_AddNumber(&mut self, op : &Op, line : usize) {
+        let (n, sub) = match op {
+            Op::AddNumber{ n, sub } => (*n, *sub),
+            _                       => panic!("internal error: execute_AddNumber() -- missing count")
+        };
+
+        let nf                    = self.settings.nrformats;
+        let mut chars : Vec<char> = self.lines[line].chars().collect();
+        let at                    = |i : usize| chars.get(i).copied().unwrap_or('\0');
+
+        let mut col = match find_number(&chars, self.cursor, nf) {
+            Some(col) => col,
+            None      => { self.failed = true; return; }
+        };
+        let first = chars[col];
+
+        if first.is_ascii_alphabetic() {
+            let (lo, hi) = if first.is_ascii_uppercase() { (b'A', b'Z') } else { (b'a', b'z') };
+            let c        = first as u8;
+            let n        = n.min(26) as u8;
+            chars[col]   = match sub {
+                true  => if c - lo < n { lo } else { c - n },
+                false => if hi - c < n { hi } else { c + n },
+            } as char;
+            self.lines[line] = chars.into_iter().collect();
+            self.cursor      = col;
+            return;
+        }
+
+        let mut negative = false;
+        if col > 0 && at(col - 1) == '-' && !nf.unsigned {
+            col     -= 1;
+            negative = true;
+        }
+
+        /* the prefix is '0' for octal, or the letter after it for hex and binary */
+        let mut i   = col + negative as usize;
+        let mut pre = None;
+        if at(i) == '0' && !matches!(at(i + 1), '8' | '9') {
+            let p = at(i + 1);
+            if (nf.hex && matches!(p, 'x' | 'X') && at(i + 2).is_ascii_hexdigit())
+            || (nf.bin && matches!(p, 'b' | 'B') && at(i + 2).is_digit(2)) {
+                pre = Some(p);
+                i  += 2;
+            } else if nf.octal && at(i + 1).is_ascii_digit() {
+                /* "08" and "0129" aren't octal */
+                let digits = chars[i + 1..].iter().take_while(|c| c.is_ascii_digit());
+                if digits.clone().all(|c| c.is_digit(8)) { pre = Some('0'); }
+            }
+        }
+
+        let radix = match pre {
+            Some('x' | 'X') => 16,
+            Some('b' | 'B') => 2,
+            Some(_)         => 8,
+            None            => 10,
+        };
+        let mut value : u64 = 0;
+        while let Some(d) = at(i).to_digit(radix) {
+            value = value.checked_mul(radix as u64).and_then(|v| v.checked_add(d as u64)).unwrap_or(u64::MAX);
+            i    += 1;
+        }
+
+        /* hex, octal and binary numbers are never negative */
+        if pre.is_some() && negative {
+            col     += 1;
+            negative = false;
+        }
+
+        let subtract = sub != negative;
+        let old      = value;
+        value = if subtract { value.wrapping_sub(n as u64) } else { value.wrapping_add(n as u64) };
+        if pre.is_none() {
+            if subtract && value > old {
+                value    = (!value).wrapping_add(1);
+                negative = !negative;
+            } else if !subtract && value < old {
+                value    = !value;
+                negative = !negative;
+            }
+            if value == 0    { negative = false; }
+        }
+        if nf.unsigned && negative {
+            value    = if subtract { 0 } else { u64::MAX };
+            negative = false;
+        }
+
+        let old_text  = &chars[col..i];
+        let upper     = old_text.iter().rev().find(|c| c.is_ascii_alphabetic()).is_some_and(|c| c.is_ascii_uppercase());
+        let mut width = old_text.len() - (old_text[0] == '-') as usize;
+
+        let mut s = String::new();
+        if negative    { s.push('-'); }
+        if let Some(p) = pre {
+            s.push('0');
+            width -= 1;
+            if p != '0' {
+                s.push(p);
+                width -= 1;
+            }
+        }
+        let digits = match pre {
+            Some('b' | 'B')  => format!("{:b}", value),
+            Some('0')        => format!("{:o}", value),
+            Some(_) if upper => format!("{:X}", value),
+            Some(_)          => format!("{:x}", value),
+            None             => value.to_string(),
+        };
+        /* a decimal number with leading zeros would look octal */
+        if first == '0' && !(nf.octal && pre.is_none()) {
+            s.extend(std::iter::repeat_n('0', width.saturating_sub(digits.len())));
+        }
+        s.push_str(&digits);
+
+        let before : String = chars[..col].iter().collect();
+        let after  : String = chars[i..].iter().collect();
+
+        self.cursor      = col + s.chars().count() - 1;
+        self.lines[line] = before + &s + &after;
+    }
+
+    fn execute_Case(&mut self, op : &Op, line : usize) {
+        let (target, kind) = match op {
+            Op::Case{ target, kind } => (target.clone(), *kind),
+            _                        => panic!("internal error: execute_Case() -- missing target")
+        };
+
+        if let Some(r) = self.get_range(&target, line) {
+            for l in r.beg.line..=r.end.line {
+                let chars : Vec<char> = self.lines[l].chars().collect();
+                let beg = if l == r.beg.line && !r.linewise { r.beg.col } else { 0 };
+                let end = if l == r.end.line && !r.linewise { r.end.col } else { chars.len() };
+
+                let first  : String = chars[..beg].iter().collect();
+                let middle : String = change_case(&chars[beg..end], kind);
+                let second : String = chars[end..].iter().collect();
+
+                self.lines[l] = first + &middle + &second;
+            }
+
+            self.line   = r.beg.line;
+            self.cursor = if r.linewise { 0 } else { r.beg.col };
+        }
+    }
+
+    /*
+     * Every line the target touches moves by a shiftwidth, except empty
+     * ones. The new indent is all spaces with expandtab, or as many tabs
+     * as fit followed by spaces.
+     */
+    fn execute_Shift(&mut self, op : &Op, line : usize) {
+        let (target, left) = match op {
+            Op::Shift{ target, left } => (target.clone(), *left),
+            _                         => panic!("internal error: execute_Shift() -- missing target")
+        };
+
+        let ts = self.settings.tabstop;
+        let sw = if self.settings.shiftwidth == 0 { ts } else { self.settings.shiftwidth };
+
+        if let Some(r) = self.get_range(&target, line) {
+            for l in r.beg.line..=r.end.line {
+                if self.lines[l].is_empty()    { continue; }
+
+                let chars : Vec<char> = self.lines[l].chars().collect();
+                let white             = chars.iter().take_while(|c| **c == ' ' || **c == '\t').count();
+                let indent            = chars[..white].iter().fold(0, |v, &c| {
+                    if c == '\t' { v + ts - v % ts } else { v + 1 }
+                });
+                let indent            = if left { indent.saturating_sub(sw) } else { indent + sw };
+
+                let mut new = if self.settings.expandtab {
+                    " ".repeat(indent)
+                } else {
+                    "\t".repeat(indent / ts) + &" ".repeat(indent % ts)
+                };
+                new.extend(&chars[white..]);
+                self.lines[l] = new;
+            }
+
+            self.line   = r.beg.line;
+            self.cursor = first_non_blank(&self.lines[r.beg.line]);
+        }
+    }
+
+    /*
+     * gq joins the lines of each paragraph in the target and breaks them
+     * again at blanks so they fit in the textwidth, 79 when it's 0.
+     * Paragraphs end at blank lines and where the comment leader changes;
+     * each new line starts with the indent or leader of the one it was
+     * broken from. The cursor goes to the last line formatted, and gw
+     * leaves it on the same text instead.
+     */
+    fn execute_Format(&mut self, op : &Op, line : usize) {
+        let (target, keep) = match op {
+            Op::Format{ target, keep } => (target.clone(), *keep),
+            _                          => panic!("internal error: execute_Format() -- missing target")
+        };
+
+        let mut at = Pos{ line, col : self.cursor };
+        if let Some(r) = self.get_range(&target, line) {
+            /* like Vim, go on to the next line if an exclusive motion stopped before it */
+            let past    = self.line > r.end.line;
+            let mut l   = r.beg.line;
+            let mut end = r.end.line;
+            while l <= end {
+                let chars : Vec<char> = self.lines[l].chars().collect();
+                let lead              = comment_leader(&chars);
+                if !in_paragraph(&chars, lead) {
+                    l += 1;
+                    continue;
+                }
+
+                while l < end && self.format_join(l, &mut at) {
+                    end -= 1;
+                }
+                while self.format_break(l, &mut at) {
+                    l   += 1;
+                    end += 1;
+                }
+                l += 1;
+            }
+
+            if keep {
+                self.line   = at.line;
+                self.cursor = at.col;
+                self.clamp_cursor(at.line);
+            } else {
+                self.line   = if past { (end + 1).min(self.lines.len() - 1) } else { end };
+                self.cursor = first_non_blank(&self.lines[self.line]);
+            }
+        }
+    }
+
+    /*
+     * Joins the line after `line` onto it, without its comment leader, if
+     * it is in the same paragraph. `at` follows the text it was on.
+     */
+    fn format_join(&mut self, line : usize, at : &mut Pos) -> bool {
+        let chars : Vec<char> = self.lines[line].chars().collect();
+        let next  : Vec<char> = self.lines[line + 1].chars().collect();
+        let lead              = comment_leader(&chars);
+        let next_lead         = comment_leader(&next);
+
+        if !in_paragraph(&next, next_lead) || !same_leader(&chars, lead, &next, next_lead) {
+            return false;
+        }
+
+        let trimmed    = next[next_lead.0..].iter().skip_while(|c| **c == ' ' || **c == '\t');
+        let removed    = next.len() - trimmed.clone().count();
+        let mut joined = self.lines[line].clone();
+        if !joined.is_empty() && !joined.ends_with(' ') && !joined.ends_with('\t')
+        && next.get(removed) != Some(&')') {
+            joined.push(' ');
+        }
+        let start = joined.chars().count();
+        joined.extend(trimmed);
+
+        if at.line == line + 1 {
+            *at = Pos{ line, col : if at.col < removed { start } else { at.col - removed + start } };
+        } else if at.line > line + 1 {
+            at.line -= 1;
+        }
+        self.join_marks(line + 1, line, start, removed);
+        self.splice_lines(line..line + 2, vec![joined]);
+        true
+    }
+
+    /*
+     * Breaks `line` at the last blanks that leave it within the textwidth,
+     * or at the first ones after its leader if a word is too long, and
+     * starts the new line with the same indent or leader. Blanks at the
+     * end of the line don't count. `at` follows the text it was on.
+     */
+    fn format_break(&mut self, line : usize, at : &mut Pos) -> bool {
+        let ts                = self.settings.tabstop;
+        let tw                = if self.settings.textwidth == 0 { 79 } else { self.settings.textwidth };
+        let blank             = |c : &char| *c == ' ' || *c == '\t';
+        let chars : Vec<char> = self.lines[line].chars().collect();
+        let len               = chars.len() - chars.iter().rev().take_while(|c| blank(c)).count();
+        let want              = col_at_vcol(&chars, tw, ts);
+        if want >= len    { return false; }
+
+        let (lead_len, kind)  = comment_leader(&chars);
+        let indent            = chars.iter().take_while(|c| blank(c)).count();
+
+        let mut split = None;
+        for beg in (indent.max(lead_len) + 1..len).rev() {
+            if blank(&chars[beg]) && !blank(&chars[beg - 1]) {
+                split = Some(beg);
+                if beg <= want    { break; }
+            }
+        }
+        let Some(beg) = split else { return false; };
+        let end = beg + chars[beg..].iter().take_while(|c| blank(c)).count();
+
+        let mut rest : String = match kind {
+            Leader::None  => chars[..indent].iter().collect(),
+            Leader::Start => chars[..indent].iter().collect::<String>() + " * ",
+            _             => chars[..lead_len].iter().collect(),
+        };
+        let prefix = rest.chars().count();
+        rest.extend(&chars[end..]);
+
+        if at.line == line && at.col >= beg {
+            *at = Pos{ line : line + 1, col : prefix + at.col.max(end) - end };
+        } else if at.line > line {
+            at.line += 1;
+        }
+        self.splice_lines(line..line + 1, vec![chars[..beg].iter().collect(), rest]);
+        true
+    }
+
+    /* a count opens that many lines with the same text */
+    fn execute_Open(&mut self, op : &Op, line : usize) {
+        let (s, n, below) = match op {
+            Op::OpenBelow{ s, n } => (s.clone(), *n as usize, true),
+            Op::OpenAbove{ s, n } => (s.clone(), *n as usize, false),
+            _                     => panic!("internal error: execute_Open() -- missing text")
+        };
+
+        let at = if below { line + 1 } else { line };
+        self.splice_lines(at..at, vec![String::new()]);
+        self.insert_text(at, 0, &vec![s; n].join("\n"));
+    }
+
+    /*
+     * J removes the indent of each following line and separates the
+     * pieces with a space, except after whitespace, before ')' or around
+     * an empty piece. gJ joins the lines as they are. The cursor goes to
+     * the last join point.
+     */
+    fn execute_Join(&mut self, op : &Op, line : usize) {
+        let (n, spaces) = match op {
+            Op::Join{ n, spaces } => ((*n).max(2) as usize, *spaces),
+            _                     => panic!("internal error: execute_Join() -- missing count")
+        };
+
+        let last = self.lines.len() - 1;
+        if line == last    { return; }
+        let end = (line + n - 1).min(last);
+
+        let mut joined = self.lines[line].clone();
+        let mut col    = 0;
+        let mut moved  = Vec::new();
+        for (l, next) in (line + 1..).zip(&self.lines[line + 1..=end]) {
+            col = joined.chars().count();
+            if spaces {
+                let trimmed = next.trim_start();
+                if !trimmed.is_empty() && !trimmed.starts_with(')') && !joined.is_empty()
+                && !joined.ends_with(' ') && !joined.ends_with('\t') {
+                    joined.push(' ');
+                }
+                moved.push((l, joined.chars().count(), next.chars().count() - trimmed.chars().count()));
+                joined.push_str(trimmed);
+            } else {
+                moved.push((l, col, 0));
+                joined.push_str(next);
+            }
+        }
+
+        for (l, start, removed) in moved {
+            self.join_marks(l, line, start, removed);
+        }
+        self.splice_lines(line..end + 1, vec![joined]);
+        self.line   = line;
+        self.cursor = col;
+        self.clamp_cursor(line);
+    }
+
+    /*
+     * :s works out its pattern, replacement and flags from the last ones
+     * where it leaves them out; & repeats the last substitute without its
+     * flags and g& repeats it everywhere with the last search pattern. A
+     * count covers that many lines, and does nothing if there aren't as
+     * many left.
+     */
+    fn execute_Substitute(&mut self, op : &Op, line : usize) {
+        let last_re  = self.last_s.as_ref().map(|s| s.re.clone());
+        let last_sub = self.last_sub.clone();
+
+        let (re, rep, flags, all, n) = match (op, last_sub) {
+            (Op::Substitute{ re, rep, flags, all, n }, last) => {
+                let prev      = last.as_ref().map_or("", |l| l.rep.as_str());
+                let mut f     = *flags;
+                if let Some(l) = last.as_ref().filter(|_| flags.keep) {
+                    f.global      = l.flags.global != flags.global;
+                    f.ignore_case = flags.ignore_case.or(l.flags.ignore_case);
+                }
+                (re.clone().or(last_re), expand_tilde(rep, prev), f, *all, *n as usize)
+            },
+            (Op::SubRepeat{ all : false, n }, Some(l)) => (Some(l.re), l.rep, SubFlags::default(), false, *n as usize),
+            (Op::SubRepeat{ all : true, .. }, Some(l)) => (last_re, l.rep, l.flags, true, 1),
+            (Op::SubRepeat{ .. }, None)                => return,
+            _ => panic!("internal error: execute_Substitute() -- missing pattern")
+        };
+
+        let re = match re {
+            Some(re) => re,
+            None     => return,
+        };
+        if !all && line + n > self.lines.len()    { return; }
+
+        self.last_sub = Some(LastSub{ re : re.clone(), rep : rep.clone(), flags });
+        match &mut self.last_s {
+            Some(s) => s.re = re.clone(),
+            None    => self.last_s = Some(LastSearch{ re : re.clone(), backward : false, offset : SearchOffset::Start(0) }),
+        }
+
+        let re = match flags.ignore_case {
+            Some(i) => RegexBuilder::new(re.as_str()).case_insensitive(i).build().unwrap(),
+            None    => re,
+        };
+
+        let lines     = if all { 0..self.lines.len() } else { line..line + n };
+        let mut new   = Vec::new();
+        let mut last  = None;
+        for l in lines.clone() {
+            let s      = &self.lines[l];
+            let expand = |caps : &Captures| expand_replacement(&rep, caps);
+            let result = if flags.global { re.replace_all(s, expand) } else { re.replacen(s, 1, expand) };
+
+            if re.is_match(s)    { last = Some(l); }
+            new.push(result.into_owned());
+        }
+
+        let last = match last {
+            Some(l) => l,
+            None    => return,
+        };
+
+        /* a replacement may break lines, which shifts the ones after it */
+        let mut last_line = 0;
+        let mut added     = 0;
+        for (l, text) in lines.zip(new) {
+            let out : Vec<String> = text.split('\n').map(String::from).collect();
+            let at                = l + added;
+            let n                 = out.len();
+            self.splice_lines(at..at + 1, out);
+            if l == last    { last_line = at + n - 1; }
+            added += n - 1;
+        }
+
+        self.line   = last_line;
+        self.cursor = first_non_blank(&self.lines[last_line]);
+    }
+
+    fn execute_Record(&mut self, op : &Op, _line : usize) {
+        if let Op::Record{ reg, keys } = op {
+            self.regs.record(*reg, keys.clone());
+        }
+    }
+
+    /*
+     * Plays the text of a register as commands. A failed motion, a
+     * register that doesn't parse, or playing too deeply stops the
+     * playback, so a large count or a macro that plays itself runs until
+     * there is nothing left to do.
+     */
+    fn execute_Play(&mut self, op : &Op, _line : usize) {
+        let (reg, n) = match op {
+            Op::Play{ reg, n } => (*reg, *n),
+            _                  => panic!("internal error: execute_Play() -- missing register")
+        };
+
+        let reg = match reg {
+            '@' => match self.last_at {
+                Some(r) => r,
+                None    => return,
+            },
+            r   => r,
+        };
+        self.last_at = Some(reg);
+
+        let text = match self.regs.get(reg) {
+            Some(r) => r.text.trim_end_matches('\n').to_owned(),
+            None    => return,
+        };
+        let mut cmd = Cmd{ esc_inserts : self.settings.esc_inserts, ..Cmd::quiet(&text) };
+        let ops     = match parse_command(&mut cmd) {
+            Some(ops) => ops,
+            None      => { self.failed = true; return; },
+        };
+
+        if self.depth >= MAX_PLAY_DEPTH {
+            self.failed = true;
+            return;
+        }
+        /* a U in the register needs to know the lines its ops changed */
+        let track_line   = self.track_line;
+        self.track_line |= ops.iter().any(|o| matches!(o, Op::UndoLine));
+        self.depth += 1;
+        'outer: for _ in 0..n {
+            for o in &ops {
+                self.step(o);
+                if self.failed    { break 'outer; }
+            }
+        }
+        self.depth -= 1;
+        self.track_line = track_line;
+    }
+
+    /*
+     * A count for '.' replaces the count of the change it repeats, and
+     * sticks for the next '.'.
+     */
+    fn execute_Repeat(&mut self, op : &Op, line : usize) {
+        let n = match op {
+            Op::Repeat{ n } => *n,
+            _               => panic!("internal error: execute_Repeat() -- missing count")
+        };
+
+        if let Some(mut o) = self.last_o.clone() {
+            if let Some(n) = n {
+                o.set_count(n);
+                self.last_o = Some(o.clone());
+            }
+            let execute_fn = self.get_execute_fn(&o);
+            execute_fn(self, &o, line);
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            lines  : self.lines.clone(),
+            empty  : self.empty,
+            cursor : Pos{ line : self.line, col : self.cursor },
+            marks  : self.marks,
+        }
+    }
+
+    /* the difference between a snapshot and the buffer, if there is one */
+    fn diff_change(&self, was : &Snapshot, cursor : Pos) -> Option<Change> {
+        let old = &was.lines;
+        if *old == self.lines && was.empty == self.empty    { return None; }
+
+        let short = old.len().min(self.lines.len());
+        let top   = old.iter().zip(&self.lines).take_while(|(a, b)| a == b).count();
+        let same  = old.iter().rev().zip(self.lines.iter().rev())
+                       .take(short - top).take_while(|(a, b)| a == b).count();
+
+        Some(Change {
+            top,
+            old    : old[top..old.len() - same].to_vec(),
+            new    : self.lines[top..self.lines.len() - same].to_vec(),
+            cursor,
+            empty  : (was.empty, self.empty),
+            marks  : was.marks,
+        })
+    }
+
+    /*
+     * A change within a single line keeps that line for U, unless U
+     * already has it; adding or removing lines at or above U's line makes
+     * U forget it. As in Vim, text typed into an opened line is what U
+     * takes back after o and O.
+     */
+    fn keep_line(&mut self, op : &Op, was : &Snapshot, change : &Change) {
+        let cursor = change.cursor;
+        let top    = change.top;
+        if let Op::OpenBelow{ s, n } | Op::OpenAbove{ s, n } = op {
+            let (line, text) = match (s.is_empty(), n) {
+                (true, _)  => (cursor.line, was.lines[cursor.line].clone()),
+                (false, 1) => (self.line, String::new()),
+                (false, n) => (self.line + 1 - *n as usize, s.clone()),
+            };
+            self.uline = Some(LineUndo{ line, text, col : 0 });
+        } else if change.old.len() == 1 && change.new.len() == 1 {
+            if self.uline.as_ref().is_none_or(|u| u.line != top) {
+                let col    = if cursor.line == top { cursor.col } else { 0 };
+                self.uline = Some(LineUndo{ line : top, text : change.old[0].clone(), col });
+            }
+        } else if change.old.len() != change.new.len() && self.uline.as_ref().is_some_and(|u| u.line >= top) {
+            self.uline = None;
+        }
+    }
+
+    /* a new undo step leaves nothing to redo */
+    fn save_change(&mut self, change : Change) {
+        self.undo.push(change);
+        self.redo.clear();
+    }
+
+    /*
+     * Like Vim, undo and redo put the cursor back where it was before the
+     * change if that is in or next to the changed lines, and otherwise on
+     * the first changed line. Marks set when the step was made come back,
+     * and the ones they replace are kept for going the other way.
+     */
+    fn restore_change(&mut self, c : &mut Change, undo : bool) {
+        let marks      = self.marks;
+        let (from, to) = if undo { (c.new.len(), c.old.clone()) } else { (c.old.len(), c.new.clone()) };
+        let size       = to.len();
+        self.splice_lines(c.top..c.top + from, to);
+        self.empty = if undo { c.empty.0 } else { c.empty.1 };
+
+        for (i, saved) in c.marks.iter_mut().enumerate() {
+            if saved.is_some()    { self.marks[i] = *saved; }
+            *saved = marks[i];
+        }
+
+        if c.cursor.line + 1 >= c.top && c.cursor.line <= c.top + size {
+            self.line   = c.cursor.line;
+            self.cursor = c.cursor.col;
+
+        } else {
+            self.line   = c.top;
+            self.cursor = usize::MAX;
+        }
+        if self.line >= self.lines.len() {
+            self.line   = self.lines.len() - 1;
+            self.cursor = 0;
+        }
+        if self.cursor == usize::MAX {
+            self.cursor = first_non_blank(&self.lines[self.line]);
+        }
+        self.clamp_cursor(self.line);
+    }
+
+    /* running out of changes to undo or redo fails */
+    fn execute_Undo(&mut self, op : &Op, _line : usize) {
+        let (n, undo) = match op {
+            Op::Undo{ n } => (*n, true),
+            Op::Redo{ n } => (*n, false),
+            _             => panic!("internal error: execute_Undo() -- missing count")
+        };
+
+        /* in a played register, what it changed so far is the step to undo */
+        if self.depth > 0 {
+            let change = self.block.take().and_then(|was| self.diff_change(&was, was.cursor));
+            if let Some(c) = change    { self.save_change(c); }
+        }
+
+        for _ in 0..n {
+            let mut c = match if undo { self.undo.pop() } else { self.redo.pop() } {
+                Some(c) => c,
+                None    => { self.failed = true; break; },
+            };
+            self.restore_change(&mut c, undo);
+            if undo { self.redo.push(c); } else { self.undo.push(c); }
+        }
+
+        /* and what it changes after starts the next one */
+        if self.depth > 0 {
+            self.block = Some(self.snapshot());
+        }
+    }
+
+    /*
+     * U swaps the line it kept with what is there now, so a second U
+     * undoes the first. It is a change of its own that u can undo.
+     */
+    fn execute_UndoLine(&mut self, _op : &Op, _line : usize) {
+        let u = match self.uline.take() {
+            Some(u) if u.line < self.lines.len() => u,
+            _                                    => { self.failed = true; return; },
+        };
+
+        let col    = if self.line == u.line { self.cursor } else { 0 };
+        let text   = std::mem::replace(&mut self.lines[u.line], u.text);
+        self.uline = Some(LineUndo{ line : u.line, text, col });
+
+        self.line   = u.line;
+        self.cursor = u.col;
+        self.clamp_cursor(u.line);
+    }
+
+    fn get_execute_fn(&self, op : &Op) -> fn(&mut Worker, &Op, usize) {
+        match op {
+            Op::Motion(_)         => Worker::execute_Motion,
+            Op::Insert     { .. } => Worker::execute_Insert,
+            Op::Append     { .. } => Worker::execute_Append,
+            Op::LineAppend { .. } => Worker::execute_LineAppend,
+            Op::Delete     { .. } => Worker::execute_Delete,
+            Op::DeleteToEnd{ .. } => Worker::execute_DeleteToEnd,
+            Op::Change     { .. } => Worker::execute_Change,
+            Op::Yank       { .. } => Worker::execute_Yank,
+            Op::Put        { .. }
+          | Op::PutBefore  { .. } => Worker::execute_Put,
+            Op::Replace    { .. } => Worker::execute_Replace,
+            Op::Overwrite  { .. } => Worker::execute_Overwrite,
+            Op::ToggleCase { .. } => Worker::execute_ToggleCase,
+            Op::Case       { .. } => Worker::execute_Case,
+            Op::Shift      { .. } => Worker::execute_Shift,
+            Op::Format     { .. } => Worker::execute_Format,
+            Op::OpenBelow  { .. }
+          | Op::OpenAbove  { .. } => Worker::execute_Open,
+            Op::Join       { .. } => Worker::execute_Join,
+            Op::Substitute { .. }
+          | Op::SubRepeat  { .. } => Worker::execute_Substitute,
+            Op::Record     { .. } => Worker::execute_Record,
+            Op::Play       { .. } => Worker::execute_Play,
+            Op::Repeat     { .. } => Worker::execute_Repeat,
+            Op::Undo       { .. }
+          | Op::Redo       { .. } => Worker::execute_Undo,
+            Op::UndoLine          => Worker::execute_UndoLine,
+            Op::Mark       { .. } => Worker::execute_Mark,
+            Op::AddNumber  { .. } => Worker::execute_AddNumber,
+            Op::Resume     { .. } => Worker::execute_Resume,
+            Op::LineInsert { .. } => Worker::execute_LineInsert,
+            Op::StartInsert{ .. } => Worker::execute_StartInsert,
+            Op::LastInsert { .. } => Worker::execute_LastInsert,
+        }
+    }
+
+    fn run(&mut self, ops : &[Op]) {
+        self.line   = 0;
+        self.cursor = 0;
+        self.want   = 0;
+        self.last_s   = None;
+        self.last_sub = None;
+        self.last_at  = None;
+        self.undo.clear();
+        self.redo.clear();
+        self.uline    = None;
+        self.marks    = [None; 26];
+        /* copying the lines for undo only pays off if something may undo them;
+           a played register can hold a u of its own */
+        self.undoable = ops.iter().any(|o| matches!(o, Op::Undo { .. } | Op::Redo { .. } | Op::UndoLine | Op::Play { .. }));
+        self.block    = None;
+        self.track_line = ops.iter().any(|o| matches!(o, Op::UndoLine));
+        self.at_eol   = None;
+        self.last_insert = None;
+
+        for op in ops {
+            self.step(op);
+        }
+    }
+
+    /*
+     * Playing a register or recording one isn't a change of its own, so
+     * '.' keeps repeating the last change made; neither are undo and redo.
+     * Ops inside a played register are part of its undo step.
+     */
+    fn step(&mut self, op : &Op) {
+        self.failed   = false;
+        self.op_start = None;
+        self.want_set = false;
+
+        /*
+         * A played register is one undo step, kept open in `block` while it
+         * runs. The ops inside it only make a snapshot of their own when a
+         * U may need the line they changed.
+         */
+        let top = self.depth == 0;
+        let own = if op.is_change() && top && self.undoable {
+            self.block = Some(self.snapshot());
+            None
+        } else if op.is_change() && !top && self.track_line && !matches!(op, Op::Play { .. } | Op::UndoLine) {
+            Some(self.snapshot())
+        } else {
+            None
+        };
+
+        let execute_fn = self.get_execute_fn(op);
+        execute_fn(self, op, self.line);
+        if let Some(was) = if top { self.block.take() } else { own } {
+            let cursor = match op {
+                Op::Play { .. } => was.cursor,
+                _               => self.op_start.unwrap_or(was.cursor),
+            };
+            if let Some(c) = self.diff_change(&was, cursor) {
+                if !matches!(op, Op::Play { .. })    { self.keep_line(op, &was, &c); }
+                if top                               { self.save_change(c); }
+            }
+        }
+
+        self.last_o = match op {
+              Op::Motion(_)
+            | Op::Repeat { .. }
+            | Op::Record { .. }
+            | Op::Play   { .. }
+            | Op::Undo   { .. }
+            | Op::Redo   { .. }
+            | Op::UndoLine
+            | Op::Mark   { .. } => self.last_o.clone(),
+            _                   => Some(op.clone())
+        };
+        self.want = match op {
+            _ if self.want_set                                 => self.want,
+            Op::Motion(Motion{ mot : SingleMotion::Down, .. })
+          | Op::Motion(Motion{ mot : SingleMotion::Up,   .. }) => self.want,
+            Op::Motion(Motion{ mot : SingleMotion::End,  .. }) => usize::MAX,
+            Op::Record { .. }                                  => self.want,
+            Op::Play   { .. }                                  => self.want,
+            Op::Mark   { .. }                                  => self.want,
+            _                                                  => self.cursor,
+        };
+    }
+
+    /*
+     * Normally each input line is a buffer of its own. In buffer mode the
+     * whole input is one buffer, which starts out as a single empty line
+     * when there is no input.
+     */
+    fn execute(&mut self, ops : &[Op]) {
+        if self.settings.buffer {
+            self.lines = std::mem::take(&mut self.input);
+            self.empty = self.lines.is_empty();
+            if self.empty    { self.lines.push(String::new()); }
+
+            self.run(ops);
+            if !self.empty    { self.output.append(&mut self.lines); }
+            return;
+        }
+
+        for l in std::mem::take(&mut self.input) {
+            if !self.settings.carry_registers {
+                self.regs = Registers::default();
+            }
+            self.lines = vec![l];
+            self.empty = false;
+
+            self.run(ops);
+            if !self.empty    { self.output.append(&mut self.lines); }
+        }
+    }
+
+    fn output(&mut self, outputer : &mut Outputer) {
+        for line in &mut self.output {
+            line.push('\n');
+            outputer.buf_wr.write_all(line.as_bytes()).unwrap();
+        }
+    }
+}
+
+struct Manager {
+    count     : usize,
+    workers   : Vec<Worker>,
+    settings  : Settings,
+}
+
+impl Manager {
+    fn new(settings : Settings) -> Manager {
+        Manager {
+            count     : 0,
+            workers   : Vec::new(),
+            settings,
+        }
+    }
+
+    fn add_worker(&mut self, lines : Vec<String>) {
+        let worker = Worker::new(self.settings.clone(), self.count, lines);
         self.workers.push(worker);
         self.count += 1;
     }
 
     fn start(&mut self, ops : Vec<Op>) {
+        /*
+         * When registers carry over, each chunk needs the registers its
+         * predecessor left behind, so the chunks run in input order.
+         */
+        if self.settings.carry_registers {
+            let mut outputer = Outputer::new();
+            let mut regs     = Registers::default();
+            for worker in &mut self.workers {
+                worker.regs = regs;
+                worker.execute(&ops);
+                regs = std::mem::take(&mut worker.regs);
+                worker.output(&mut outputer);
+            }
+            return;
+        }
+
         let outputer  = Arc::new(Mutex::new(Outputer::new()));
         let _outputer = outputer.clone(); /* one ref count */
         self.workers.par_iter_mut().for_each(|worker| {
@@ -388,10 +2428,7 @@ impl Manager {
             loop {
                 let mut guarded_outputer = outputer.lock().unwrap();
                 if guarded_outputer.allow == worker.pos {
-                    for line in &mut worker.lines {
-                        line.push('\n');
-                        guarded_outputer.buf_wr.write(line.as_bytes()).unwrap();
-                    }
+                    worker.output(&mut guarded_outputer);
 
                     guarded_outputer.allow += 1;
                     break;
@@ -401,13 +2438,25 @@ impl Manager {
     }
 }
 
-pub fn execute(ops : Vec<Op>) {
+pub fn execute(ops : Vec<Op>, settings : Settings) {
+    /*
+     * In buffer mode the command sees the whole input at once, so there
+     * is nothing to split up between workers.
+     */
+    if settings.buffer {
+        let lines      = io::stdin().lock().lines().map(|l| l.unwrap()).collect();
+        let mut worker = Worker::new(settings, 0, lines);
+        worker.execute(&ops);
+        worker.output(&mut Outputer::new());
+        return;
+    }
+
     let mut line : String;
 
     let mut lines   = Vec::new();
     let page_sz     = page_size::get();
     let mut size    = 0;
-    let mut manager = Manager::new();
+    let mut manager = Manager::new(settings);
 
     for _line in io::stdin().lock().lines() {
         line = _line.unwrap();
@@ -426,9 +2475,790 @@ pub fn execute(ops : Vec<Op>) {
             lines.push(line);
         }
     }
-    if lines.len() > 0 {
+    if !lines.is_empty() {
         manager.add_worker(lines);
     }
 
     manager.start(ops);
 }
+
+/*
+ * Case mapping goes through the full Unicode mappings, so a character may
+ * turn into several (e.g. 'ß' uppercases to "SS").
+ */
+fn change_case(chars : &[char], kind : CaseOp) -> String {
+    let mut s = String::new();
+    for &c in chars {
+        match kind {
+            CaseOp::Upper                        => s.extend(c.to_uppercase()),
+            CaseOp::Lower                        => s.extend(c.to_lowercase()),
+            CaseOp::Toggle if c.is_lowercase()   => s.extend(c.to_uppercase()),
+            CaseOp::Toggle if c.is_uppercase()   => s.extend(c.to_lowercase()),
+            CaseOp::Toggle                       => s.push(c),
+        }
+    }
+    s
+}
+
+/*
+ * ~ in a replacement stands for the previous replacement; \~ is kept for
+ * expand_replacement() to turn into a plain ~.
+ */
+fn expand_tilde(rep : &str, prev : &str) -> String {
+    let mut s     = String::new();
+    let mut chars = rep.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '~'  => s.push_str(prev),
+            '\\' => { s.push(c); if let Some(e) = chars.next()    { s.push(e); } },
+             _   => s.push(c),
+        }
+    }
+    s
+}
+
+/*
+ * Builds the text for one match: & and \0 to \9 insert groups, \u and \l
+ * change the case of the next character, \U and \L of everything up to
+ * \e or \E, and \r or \n break the line.
+ */
+fn expand_replacement(rep : &str, caps : &Captures) -> String {
+    let mut s                       = String::new();
+    let mut one  : Option<CaseOp>   = None;
+    let mut span : Option<CaseOp>   = None;
+    let mut chars                   = rep.chars();
+
+    fn push(s : &mut String, one : &mut Option<CaseOp>, span : Option<CaseOp>, text : &str) {
+        for c in text.chars() {
+            match one.take().or(span) {
+                Some(kind) => s.push_str(&change_case(&[c], kind)),
+                None       => s.push(c),
+            }
+        }
+    }
+
+    while let Some(c) = chars.next() {
+        if c == '&' {
+            push(&mut s, &mut one, span, &caps[0]);
+            continue;
+        }
+        if c != '\\' {
+            push(&mut s, &mut one, span, &c.to_string());
+            continue;
+        }
+        match chars.next() {
+            Some(d @ '0'..='9') => {
+                let g = caps.get(d.to_digit(10).unwrap() as usize).map_or("", |m| m.as_str());
+                push(&mut s, &mut one, span, g);
+            },
+            Some('u')             => one  = Some(CaseOp::Upper),
+            Some('l')             => one  = Some(CaseOp::Lower),
+            Some('U')             => span = Some(CaseOp::Upper),
+            Some('L')             => span = Some(CaseOp::Lower),
+            Some('e') | Some('E') => span = None,
+            Some('r') | Some('n') => s.push('\n'),
+            Some('t')             => push(&mut s, &mut one, span, "\t"),
+            Some(e)               => push(&mut s, &mut one, span, &e.to_string()),
+            None                  => push(&mut s, &mut one, span, "\\"),
+        }
+    }
+    s
+}
+
+/*
+ * A word from 'matchwords' found in a line: its place in its group, with
+ * 0 the first word of the group.
+ */
+struct Keyword {
+    beg   : usize,
+    end   : usize,
+    group : usize,
+    role  : usize,
+}
+
+fn line_keywords(chars : &[char], groups : &[Vec<String>]) -> Vec<Keyword> {
+    let mut found = Vec::new();
+    let mut i     = 0;
+
+    while i < chars.len() {
+        if char_class(chars[i], false) != 2 {
+            i += 1;
+            continue;
+        }
+        let beg = i;
+        while i < chars.len() && char_class(chars[i], false) == 2    { i += 1; }
+
+        let word : String = chars[beg..i].iter().collect();
+        for (group, words) in groups.iter().enumerate() {
+            if let Some(role) = words.iter().position(|w| *w == word) {
+                found.push(Keyword{ beg, end : i, group, role });
+                break;
+            }
+        }
+    }
+    found
+}
+
+fn is_escaped(chars : &[char], i : usize) -> bool {
+    chars[..i].iter().rev().take_while(|&&c| c == '\\').count() % 2 == 1
+}
+
+/*
+ * Which characters are between double quotes, as long as the quotes on
+ * the line pair up.
+ */
+fn quote_mask(chars : &[char]) -> Vec<bool> {
+    let quotes : Vec<usize> = (0..chars.len()).filter(|&i| chars[i] == '"' && !is_escaped(chars, i)).collect();
+    let mut mask            = vec![false; chars.len()];
+
+    if quotes.len().is_multiple_of(2) {
+        for pair in quotes.chunks(2) {
+            for m in &mut mask[pair[0]..pair[1]]    { *m = true; }
+        }
+    }
+    mask
+}
+
+/*
+ * Parts of comments a formatted line carries on, from Vim's default
+ * 'comments': "#" and "//" lines, and the start, middle "*" and end of a
+ * C comment.
+ */
+#[derive(Copy, Clone, PartialEq)]
+enum Leader {
+    None,
+    Plain,
+    Start,
+    Middle,
+    End,
+}
+
+/*
+ * How long the comment leader of a line is, counting the indent before it
+ * and the blanks after it. "#" and a middle "*" need a blank after them.
+ */
+fn comment_leader(chars : &[char]) -> (usize, Leader) {
+    let blank  = |c : &char| *c == ' ' || *c == '\t';
+    let indent = chars.iter().take_while(|c| blank(c)).count();
+    let rest   = &chars[indent..];
+    let spaced = |n : usize| rest.get(n).is_none_or(blank);
+
+    let (n, kind) = match rest {
+        ['/', '*', ..]        => (2, Leader::Start),
+        ['*', '/', ..]        => (2, Leader::End),
+        ['/', '/', ..]        => (2, Leader::Plain),
+        ['*', ..] if spaced(1) => (1, Leader::Middle),
+        ['#', ..] if spaced(1) => (1, Leader::Plain),
+        _                     => return (0, Leader::None),
+    };
+    (indent + n + rest[n..].iter().take_while(|c| blank(c)).count(), kind)
+}
+
+/* lines with nothing after their leader and comment ends separate paragraphs */
+fn in_paragraph(chars : &[char], (len, kind) : (usize, Leader)) -> bool {
+    kind != Leader::End && chars[len..].iter().any(|c| *c != ' ' && *c != '\t')
+}
+
+/*
+ * Whether two lines with these leaders belong to the same paragraph: a
+ * comment's start only goes on with its middle, and otherwise the leaders
+ * must match apart from their blanks.
+ */
+fn same_leader(first : &[char], (len1, kind1) : (usize, Leader), second : &[char], (len2, kind2) : (usize, Leader)) -> bool {
+    match kind1 {
+        Leader::End   => return false,
+        Leader::Start => return first.len() > len1 && kind2 == Leader::Middle,
+        _             => {},
+    }
+
+    let text = |chars : &[char]| chars.iter().filter(|c| **c != ' ' && **c != '\t').copied().collect::<Vec<char>>();
+    text(&first[..len1]) == text(&second[..len2])
+}
+
+/*
+ * Index of the character that covers display column `vcol` (counting
+ * from 0), or the length of the line if it is too short.
+ */
+fn col_at_vcol(chars : &[char], vcol : usize, tabstop : usize) -> usize {
+    let mut v = 0;
+    for (i, &c) in chars.iter().enumerate() {
+        v += if c == '\t' { tabstop - v % tabstop } else { 1 };
+        if v > vcol    { return i; }
+    }
+    chars.len()
+}
+
+/*
+ * Where Ctrl-A finds its number, as in Vim: a hex or binary number the
+ * cursor is in starts at its "0x" or "0b", otherwise it is the first
+ * digit at or after the cursor, or the start of the digits the cursor is
+ * on. With 'alpha' a letter is taken as well.
+ */
+fn find_number(chars : &[char], cursor : usize, nf : NrFormats) -> Option<usize> {
+    let at      = |i : usize| chars.get(i).copied().unwrap_or('\0');
+    let is_hex  = |col : usize| col > 0 && matches!(at(col), 'x' | 'X') && at(col - 1) == '0' && at(col + 1).is_ascii_hexdigit();
+    let is_bin  = |col : usize| col > 0 && matches!(at(col), 'b' | 'B') && at(col - 1) == '0' && at(col + 1).is_digit(2);
+    let is_word = |c : char| c.is_ascii_digit() || (nf.alpha && c.is_ascii_alphabetic());
+
+    let mut col = cursor;
+    if nf.bin    { while col > 0 && at(col).is_digit(2)          { col -= 1; } }
+    if nf.hex    { while col > 0 && at(col).is_ascii_hexdigit()  { col -= 1; } }
+    if nf.bin && nf.hex && !is_hex(col) {
+        col = cursor;
+        while col > 0 && at(col).is_ascii_digit()    { col -= 1; }
+    }
+
+    if (nf.hex && is_hex(col)) || (nf.bin && is_bin(col)) {
+        col -= 1;
+    } else {
+        col = cursor;
+        while col < chars.len() && !is_word(chars[col])    { col += 1; }
+        while col > 0 && at(col - 1).is_ascii_digit() && !(nf.alpha && at(col).is_ascii_alphabetic()) {
+            col -= 1;
+        }
+    }
+
+    if is_word(at(col)) { Some(col) } else { None }
+}
+
+/*
+ * Where Vim puts the cursor when moving to a line: on the first non-blank,
+ * or on the last character of a blank line.
+ */
+fn first_non_blank(s : &str) -> usize {
+    let len = s.chars().count();
+    s.chars().position(|c| !c.is_whitespace()).unwrap_or(len.saturating_sub(1))
+}
+
+fn char_class(c : char, big : bool) -> u8 {
+    if c.is_whitespace()                    { 0 }
+    else if big                             { 1 }
+    else if c.is_alphanumeric() || c == '_' { 2 }
+    else                                    { 1 }
+}
+
+/*
+ * Steps through a buffer the way Vim's word motions do. The end of each
+ * line is a position of its own that counts as a blank, so words never
+ * run across lines, and an empty line counts as a word.
+ */
+struct WordCursor<'a> {
+    lines : &'a [String],
+    chars : Vec<char>,
+    pos   : Pos,
+    big   : bool,
+}
+
+impl<'a> WordCursor<'a> {
+    fn new(lines : &'a [String], pos : Pos, big : bool) -> WordCursor<'a> {
+        WordCursor { lines, chars : lines[pos.line].chars().collect(), pos, big }
+    }
+
+    fn cls(&self) -> u8 {
+        match self.chars.get(self.pos.col) {
+            Some(&c) => char_class(c, self.big),
+            None     => 0,
+        }
+    }
+
+    fn on_empty_line(&self) -> bool { self.chars.is_empty() }
+
+    /*
+     * Like Vim's inc() and dec(): 0 for a step within the line, 1 for a
+     * step onto another line, 2 for a step onto the end of the line, and
+     * -1 at either end of the buffer.
+     */
+    fn inc(&mut self) -> i32 {
+        if self.pos.col < self.chars.len() {
+            self.pos.col += 1;
+            return if self.pos.col < self.chars.len() { 0 } else { 2 };
+        }
+        if self.pos.line + 1 < self.lines.len() {
+            self.pos   = Pos{ line : self.pos.line + 1, col : 0 };
+            self.chars = self.lines[self.pos.line].chars().collect();
+            return 1;
+        }
+        -1
+    }
+
+    fn dec(&mut self) -> i32 {
+        if self.pos.col > 0 {
+            self.pos.col -= 1;
+            return 0;
+        }
+        if self.pos.line > 0 {
+            self.chars = self.lines[self.pos.line - 1].chars().collect();
+            self.pos   = Pos{ line : self.pos.line - 1, col : self.chars.len() };
+            return 1;
+        }
+        -1
+    }
+
+    /* skips characters of a class, true if the buffer ran out */
+    fn skip(&mut self, class : u8, forward : bool) -> bool {
+        while self.cls() == class {
+            if (if forward { self.inc() } else { self.dec() }) == -1    { return true; }
+        }
+        false
+    }
+
+    /* w and W; with `eol` the last count stops at the end of the line */
+    fn fwd_word(&mut self, count : u32, eol : bool) -> bool {
+        for n in (0..count).rev() {
+            let sclass = self.cls();
+            let stop   = |i : i32| i == -1 || (i >= 1 && eol && n == 0);
+
+            let last_line = self.pos.line + 1 == self.lines.len();
+            let i         = self.inc();
+            if i == -1 || (i >= 1 && last_line) { return false; }
+            if i >= 1 && eol && n == 0          { return true; }
+
+            if sclass != 0 {
+                while self.cls() == sclass {
+                    if stop(self.inc())    { return true; }
+                }
+            }
+            while self.cls() == 0 {
+                if self.pos.col == 0 && self.on_empty_line()    { break; }
+                if stop(self.inc())    { return true; }
+            }
+        }
+        true
+    }
+
+    /* b and B */
+    fn bck_word(&mut self, count : u32) -> bool {
+        for _ in 0..count {
+            if self.dec() == -1    { return false; }
+
+            let mut finished = false;
+            while self.cls() == 0 {
+                if self.pos.col == 0 && self.on_empty_line() {
+                    finished = true;
+                    break;
+                }
+                if self.dec() == -1    { return true; }
+            }
+            if finished    { continue; }
+
+            if self.skip(self.cls(), false)    { return true; }
+            self.inc();
+        }
+        true
+    }
+
+    /*
+     * e and E. With `stop`, a cursor already on the end of a word stays
+     * there for the first count; with `empty`, empty lines count as words.
+     */
+    fn end_word(&mut self, count : u32, stop : bool, empty : bool) -> bool {
+        let mut stop = stop;
+
+        for _ in 0..count {
+            let sclass = self.cls();
+            if self.inc() == -1    { return false; }
+
+            let mut finished = false;
+            if self.cls() == sclass && sclass != 0 {
+                if self.skip(sclass, true)    { return false; }
+            } else if !stop || sclass == 0 {
+                while self.cls() == 0 {
+                    if self.pos.col == 0 && self.on_empty_line() && empty {
+                        finished = true;
+                        break;
+                    }
+                    if self.inc() == -1    { return false; }
+                }
+                if !finished && self.skip(self.cls(), true)    { return false; }
+            }
+            if !finished    { self.dec(); }
+            stop = false;
+        }
+        true
+    }
+
+    /* ge and gE */
+    fn bckend_word(&mut self, count : u32) -> bool {
+        for _ in 0..count {
+            let sclass = self.cls();
+            if self.dec() == -1    { return false; }
+
+            if sclass != 0 {
+                while self.cls() == sclass {
+                    if self.dec() == -1    { return true; }
+                }
+            }
+            while self.cls() == 0 {
+                if self.pos.col == 0 && self.on_empty_line()    { break; }
+                if self.dec() == -1    { return true; }
+            }
+        }
+        true
+    }
+}
+
+/*
+ * iw/aw and iW/aW, following Vim's current_word(): a count alternates
+ * between words and the whitespace between them, and "a word" takes the
+ * trailing whitespace, or the leading whitespace when there is none after
+ * the word (but never indentation).
+ */
+fn word_object(chars : &[char], cursor : usize, count : u32, inner : bool, big : bool) -> Option<(usize, usize)> {
+    let len     = chars.len();
+    let cls     = |i : usize| char_class(chars[i], big);
+    let run_end = |i : usize| {
+        let mut j = i;
+        while j + 1 < len && cls(j + 1) == cls(i)    { j += 1; }
+        j
+    };
+
+    let mut start = cursor;
+    while start > 0 && cls(start - 1) == cls(cursor)    { start -= 1; }
+
+    let mut include_white = false;
+    let mut end           = run_end(cursor);
+
+    if !inner {
+        if cls(cursor) == 0 {
+            if end + 1 >= len    { return None; }
+            end = run_end(end + 1);
+        } else {
+            if end + 1 < len && cls(end + 1) == 0    { end = run_end(end + 1); }
+            include_white = true;
+        }
+    }
+
+    for _ in 1..count {
+        let pos = end + 1;
+        if pos >= len    { return None; }
+        end = run_end(pos);
+        if !inner {
+            if cls(pos) == 0 {
+                if end + 1 >= len    { return None; }
+                end = run_end(end + 1);
+            } else if end + 1 < len && cls(end + 1) == 0 {
+                end = run_end(end + 1);
+            }
+        }
+    }
+
+    if include_white && cls(end) != 0 && start > 0 && cls(start - 1) == 0 {
+        let mut s = start - 1;
+        while s > 0 && cls(s - 1) == 0    { s -= 1; }
+        if s > 0    { start = s; }
+    }
+
+    Some((start, end + 1))
+}
+
+/*
+ * i"/a" and friends, following Vim's current_quote(): when the cursor is
+ * on a quote, pair the quotes up from the start of the line to tell opening
+ * from closing; otherwise use the nearest quote before the cursor, or the
+ * first one after it. Backslash-escaped quotes are skipped.
+ */
+fn quote_object(chars : &[char], cursor : usize, q : char, count : u32, inner : bool) -> Option<(usize, usize)> {
+    let len = chars.len();
+
+    let next_quote = |from : usize| {
+        let mut i = from;
+        while i < len {
+            if chars[i] == '\\'    { i += 2; continue; }
+            if chars[i] == q       { return Some(i); }
+            i += 1;
+        }
+        None
+    };
+    let prev_quote = |from : usize| {
+        let mut i = from;
+        while i > 0 {
+            i -= 1;
+            let mut n = 0;
+            while i > n && chars[i - n - 1] == '\\'    { n += 1; }
+            if n % 2 == 0 && chars[i] == q    { return Some(i); }
+        }
+        None
+    };
+
+    let (mut start, mut end);
+
+    if chars[cursor] == q {
+        start = 0;
+        loop {
+            start = next_quote(start)?;
+            if start > cursor    { return None; }
+            end = next_quote(start + 1)?;
+            if cursor <= end    { break; }
+            start = end + 1;
+        }
+    } else {
+        start = match prev_quote(cursor) {
+            Some(p) => p,
+            None    => next_quote(cursor)?
+        };
+        end = next_quote(start + 1)?;
+    }
+
+    if inner && count == 1    { return Some((start + 1, end)); }
+
+    if !inner {
+        if end + 1 < len && chars[end + 1].is_whitespace() {
+            while end + 1 < len && chars[end + 1].is_whitespace()    { end += 1; }
+        } else {
+            while start > 0 && chars[start - 1].is_whitespace()    { start -= 1; }
+        }
+    }
+
+    Some((start, end + 1))
+}
+
+/*
+ * i(/a( and friends: find the unmatched opening bracket `count` levels
+ * around the cursor and its partner, failing if there aren't that many. A
+ * cursor on either bracket selects the block that bracket belongs to.
+ */
+fn block_object(chars : &[char], cursor : usize, open : char, close : char, count : u32, inner : bool) -> Option<(usize, usize)> {
+    let mut pos   = if chars[cursor] == open { cursor + 1 } else { cursor };
+    let mut start = None;
+
+    for _ in 0..count {
+        let mut depth = 0;
+        let mut found = None;
+        let mut i     = pos;
+        while i > 0 {
+            i -= 1;
+            if chars[i] == close {
+                depth += 1;
+            } else if chars[i] == open {
+                if depth == 0    { found = Some(i); break; }
+                depth -= 1;
+            }
+        }
+        match found {
+            Some(i) => { start = Some(i); pos = i; },
+            None    => return None
+        }
+    }
+
+    let start     = start?;
+    let mut depth = 0;
+    let mut end   = None;
+    for (i, &c) in chars.iter().enumerate().skip(start + 1) {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            if depth == 0    { end = Some(i); break; }
+            depth -= 1;
+        }
+    }
+    let end = end?;
+
+    if inner    { Some((start + 1, end)) }
+    else        { Some((start, end + 1)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* runs a command over some input the way main() does, giving back the lines written out */
+    fn run_with(settings : Settings, cmd : &str, input : &[&str]) -> Vec<String> {
+        let mut cmd    = Cmd{ esc_inserts : settings.esc_inserts, ..Cmd::new(cmd) };
+        let ops        = parse_command(&mut cmd).unwrap();
+        let mut worker = Worker::new(settings, 0, input.iter().map(|l| l.to_string()).collect());
+        worker.execute(&ops);
+        worker.output
+    }
+
+    fn norm(cmd : &str, input : &[&str]) -> Vec<String> {
+        run_with(Settings::default(), cmd, input)
+    }
+
+    fn buffer(cmd : &str, input : &[&str]) -> Vec<String> {
+        run_with(Settings{ buffer : true, ..Settings::default() }, cmd, input)
+    }
+
+    #[test]
+    fn percent_matching() {
+        assert_eq!(norm("d%", &["f(a(b)c) x"]), [" x"]);
+        assert_eq!(norm("fbd%", &["f(a(b)c) x"]), ["f(a)c) x"]);
+        assert_eq!(norm("y%P", &["f(a) x"]), ["f(a)f(a) x"]);
+        assert_eq!(norm("d%", &["f(a, \")\", b) x"]), [" x"]);
+        assert_eq!(norm("$d%", &["{[(x)]}"]), [""]);
+        assert_eq!(norm("d%", &["no brackets"]), ["no brackets"]);
+        assert_eq!(buffer("d%", &["{", "  x", "} y", "z"]), [" y", "z"]);
+
+        /* an operator takes in all of a word % goes forward to, but not back */
+        let words = Settings{ matchwords : vec![vec!["if".into(), "fi".into()], vec!["begin".into(), "else".into(), "end".into()]],
+                              ..Settings::default() };
+        assert_eq!(run_with(words.clone(), "d%", &["if a; if b; fi; fi x"]), [" x"]);
+        assert_eq!(run_with(words.clone(), "wwwd%", &["if a; if b; fi; fi x"]), ["if a; ; fi x"]);
+        assert_eq!(run_with(words.clone(), "17|d%", &["if a; if b; fi; fi x"]), ["i x"]);
+        assert_eq!(run_with(words.clone(), "%d%", &["begin a else b end c"]), ["begin a  c"]);
+        assert_eq!(run_with(words, "$bd%", &["begin a else b end c"]), ["nd c"]);
+    }
+
+    #[test]
+    fn undo_in_played_register() {
+        let abc   = &["abcdef"];
+        let lines = &["abcdef", "ghijkl", "mnopqr", "stuvwx"];
+        assert_eq!(norm("qaxuq@a", abc), ["abcdef"]);
+        assert_eq!(norm("qaxxuq@a", abc), ["bcdef"]);
+        assert_eq!(norm("qaxxuq@au", abc), ["abcdef"]);
+        assert_eq!(norm("qaxuxq@au", abc), ["bcdef"]);
+        assert_eq!(norm("qaxq2@au", abc), ["bcdef"]);
+        assert_eq!(norm("qaxxuq2@a", abc), ["bcdef"]);
+        assert_eq!(norm("qaxu<C-r>q@a", abc), ["cdef"]);
+        assert_eq!(norm("qaxu<C-r>q@au", abc), ["bcdef"]);
+        assert_eq!(buffer("qaxjxUq@a", lines), ["bcdef", "hijkl", "mnopqr", "stuvwx"]);
+        assert_eq!(buffer("qaxjxUq@au", lines), ["bcdef", "ghijkl", "mnopqr", "stuvwx"]);
+        assert_eq!(buffer("qaxjxq@aU", lines), ["bcdef", "ijkl", "mnopqr", "stuvwx"]);
+        assert_eq!(buffer("qaxjxkq@aU", lines), ["cdef", "hijkl", "mnopqr", "stuvwx"]);
+    }
+
+    #[test]
+    fn marks_after_line_changes() {
+        let lines = &["one", "two", "three", "four"];
+        assert_eq!(buffer("jjmaggdd`ax", lines), ["two", "hree", "four"]);
+        assert_eq!(buffer("jjmaggdd'ax", lines), ["two", "hree", "four"]);
+        assert_eq!(buffer("jjlmakkdd`ax", lines), ["two", "tree", "four"]);
+        assert_eq!(buffer("jjmaggdj`ax", lines), ["hree", "four"]);
+        assert_eq!(buffer("jjmajdd`ax", lines), ["one", "two", "hree"]);
+        assert_eq!(buffer("jjmaggO/<CR>/`ax", lines), ["", "", "one", "two", "hree", "four"]);
+        assert_eq!(buffer("jjmakJ`ax", lines), ["one", "two hree", "four"]);
+        /* the mark goes with its line, so there is nowhere to go back to */
+        assert_eq!(buffer("jjmaddgg`ax", lines), ["ne", "two", "four"]);
+    }
+
+    #[test]
+    fn increment_number_formats() {
+        let with = |nf : &str, cmd : &str, input : &str| {
+            let mut settings = Settings::default();
+            settings.set(&format!("nrformats={}", nf)).unwrap();
+            run_with(settings, cmd, &[input])
+        };
+        assert_eq!(norm("<C-a>", &["x 0x0f y"]), ["x 0x10 y"]);
+        assert_eq!(norm("<C-a>", &["x 0xFF y"]), ["x 0x100 y"]);
+        assert_eq!(norm("5<C-x>", &["0x10"]), ["0x0b"]);
+        assert_eq!(norm("<C-a>", &["-0x10"]), ["-0x11"]);
+        assert_eq!(norm("<C-a>", &["007"]), ["010"]);
+        assert_eq!(norm("<C-x>", &["010"]), ["007"]);
+        assert_eq!(norm("<C-a>", &["09"]), ["10"]);
+        assert_eq!(norm("<C-a>", &["0b101"]), ["0b110"]);
+
+        assert_eq!(with("", "<C-a>", "x 0x0f y"), ["x 1x0f y"]);
+        assert_eq!(with("", "5<C-x>", "0x10"), ["-5x10"]);
+        assert_eq!(with("", "<C-a>", "007"), ["008"]);
+        assert_eq!(with("", "<C-a>", "0b101"), ["1b101"]);
+        assert_eq!(with("hex", "<C-a>", "0x0f"), ["0x10"]);
+        assert_eq!(with("hex", "<C-a>", "0777"), ["0778"]);
+        assert_eq!(with("alpha", "<C-a>", "x 0x0f y"), ["y 0x0f y"]);
+        assert_eq!(with("bin,unsigned", "<C-a>", "x-8"), ["x-9"]);
+        assert_eq!(with("bin,unsigned", "<C-a>", "-0x10"), ["-1x10"]);
+    }
+
+    #[test]
+    fn inserted_line_breaks() {
+        assert_eq!(norm("wi/<CR>/x", &["one two"]), ["one ", "wo"]);
+        assert_eq!(norm("3li/<CR>x<CR>y/kx", &["abcdef"]), ["abc", "", "ydef"]);
+        assert_eq!(norm("A/<CR>end/bx", &["abc def"]), ["abc def", "nd"]);
+        assert_eq!(norm("wi/<CR>/dd", &["one two"]), ["one "]);
+        assert_eq!(norm("o/xyz/0x", &["abc"]), ["abc", "yz"]);
+        assert_eq!(norm("O/xyz/jx", &["abc"]), ["xyz", "ab"]);
+        assert_eq!(norm("wi/<CR>/x", &["one two", "ab cd"]), ["one ", "wo", "ab ", "d"]);
+        assert_eq!(buffer("wi/<CR>/jx", &["one two", "three"]), ["one ", "two", "hree"]);
+    }
+
+    #[test]
+    fn format_to_textwidth() {
+        let tw = |width : usize| Settings{ textwidth : width, ..Settings::default() };
+        assert_eq!(run_with(tw(20), "gqq", &["// one two three four five six seven"]),
+                   ["// one two three", "// four five six", "// seven"]);
+        assert_eq!(run_with(tw(12), "gqq", &["  // one two three four five six"]),
+                   ["  // one two", "  // three", "  // four", "  // five", "  // six"]);
+        assert_eq!(run_with(tw(20), "gqq", &[" * a star comment that is quite long"]),
+                   [" * a star comment", " * that is quite", " * long"]);
+        assert_eq!(run_with(tw(20), "gqq", &["    indented words go on and on here"]),
+                   ["    indented words", "    go on and on", "    here"]);
+        assert_eq!(run_with(tw(20), "gqq", &["short"]), ["short"]);
+
+        let buffer = Settings{ buffer : true, ..tw(20) };
+        assert_eq!(run_with(buffer.clone(), "gqG", &["// one two", "// three", "", "plain text here and more"]),
+                   ["// one two three", "", "plain text here and", "more"]);
+        assert_eq!(run_with(buffer, "gqj", &["a b", "c d e f g h i j k l m n o p"]),
+                   ["a b c d e f g h i j", "k l m n o p"]);
+    }
+
+    #[test]
+    fn registers() {
+        /* deleted lines shift through "1 to "9, small deletes go to "- */
+        assert_eq!(buffer("dddd\"1p\"2p", &["a", "b", "c"]), ["c", "b", "a"]);
+        assert_eq!(buffer("dddddd\"3p\"2p\"1p", &["a", "b", "c", "d"]), ["d", "a", "b", "c"]);
+        assert_eq!(buffer("ddx\"-p\"1p", &["ab", "cd"]), ["dc", "ab"]);
+        assert_eq!(buffer("d3w\"1p", &["a b c d"]), ["d"]);
+
+        /* an uppercase name appends, "0 keeps the last yank */
+        assert_eq!(norm("\"ayiww\"Ayiw$\"ap", &["foo bar"]), ["foo barfoobar"]);
+        assert_eq!(buffer("\"add\"Ayyj\"ap", &["a", "b", "c"]), ["b", "c", "a", "b"]);
+        assert_eq!(buffer("yyddjp\"0p", &["a", "b", "c"]), ["b", "c", "a", "a"]);
+
+        /* the black hole register leaves the others alone */
+        assert_eq!(norm("yiww\"_dwP", &["foo bar"]), ["foofoo "]);
+        assert_eq!(buffer("yiwdw\"_ddP", &["foo bar", "z"]), ["foo z"]);
+    }
+
+    #[test]
+    fn carried_registers() {
+        let carry = Settings{ carry_registers : true, ..Settings::default() };
+        assert_eq!(run_with(carry.clone(), "P0yiw", &["a", "b", "c"]), ["a", "ab", "abc"]);
+        assert_eq!(run_with(carry, "\"Ayl$\"ap", &["a", "b", "c"]), ["aa", "bab", "cabc"]);
+        assert_eq!(norm("\"Ayl$\"ap", &["a", "b", "c"]), ["aa", "bb", "cc"]);
+    }
+
+    #[test]
+    fn substitute_repeats_and_case() {
+        assert_eq!(buffer(":s/a/X/<CR>jg&", &["aa", "bab", "axa"]), ["XX", "bXb", "Xxa"]);
+        assert_eq!(buffer(":s/a/X/g<CR>j&", &["aa", "aa"]), ["XX", "Xa"]);
+        assert_eq!(buffer(":s/a/X/<CR>j:s/b/~Y/<CR>", &["a", "b"]), ["X", "XY"]);
+        assert_eq!(buffer(r":s/a/X/<CR>j:s/b/\~Y/<CR>", &["a", "b"]), ["X", "~Y"]);
+        assert_eq!(norm(r":s/\w\+/\u&/g<CR>", &["foo bar"]), ["Foo Bar"]);
+        assert_eq!(norm(r":s/\w\+/\L\u&/g<CR>", &["FOO BAR"]), ["Foo Bar"]);
+        assert_eq!(norm(r":s/\(\w\+\) \(\w\+\)/\U\2\E \1/<CR>", &["foo bar"]), ["BAR foo"]);
+        assert_eq!(norm(r":s/o\+/\U&x\ey/<CR>", &["foo"]), ["fOOXy"]);
+    }
+
+    #[test]
+    fn text_object_edges() {
+        assert_eq!(norm("4|ciw/X/", &["foo   bar"]), ["fooXbar"]);
+        assert_eq!(norm("$ciw/X/", &["foo bar"]), ["foo X"]);
+        assert_eq!(norm("wc2iw/X/", &["a foo bar"]), ["a Xbar"]);
+        assert_eq!(norm("di\"", &["say \"hi\" now"]), ["say \"\" now"]);
+        assert_eq!(norm("di\"", &["a \"b\\\"c\" d"]), ["a \"\" d"]);
+        assert_eq!(norm("$di\"", &["x \"ab\" y"]), ["x \"ab\" y"]);
+        assert_eq!(norm("da\"", &["x \"a\" y"]), ["x y"]);
+        assert_eq!(norm("$da\"", &["x \"a\""]), ["x"]);
+        assert_eq!(norm("fbda(", &["f(a, (b), c)"]), ["f(a, , c)"]);
+        assert_eq!(norm("fbd2a(", &["f(a, (b), c)"]), ["f"]);
+        assert_eq!(norm("fbd3a(", &["f(a, (b), c)"]), ["f(a, (b), c)"]);
+        assert_eq!(norm("f(da(", &["f(a) b"]), ["f b"]);
+        assert_eq!(norm("da(", &["no parens"]), ["no parens"]);
+    }
+
+    #[test]
+    fn change_on_empty_line_inserts() {
+        for cmd in ["C/new/", "s/new/", "cl/new/", "cw/new/", "ciw/new/"] {
+            assert_eq!(norm(cmd, &[""]), ["new"], "{}", cmd);
+        }
+        assert_eq!(norm("cf(/new/", &[""]), [""]);
+    }
+
+    #[test]
+    fn overwrite_editing_keys() {
+        assert_eq!(norm("R/a<BS>b/", &["one two"]), ["bne two"]);
+        assert_eq!(norm("$R/abc<C-w>Q/", &["one two"]), ["one twQ"]);
+        assert_eq!(norm("wR/xyz<C-u><C-u>Q/", &["one two"]), ["Qne two"]);
+        assert_eq!(norm("wR/ab<CR>cd<BS><BS><BS>Q/", &["one two"]), ["one abQ"]);
+        assert_eq!(norm("yiwwR/a<C-r>\"Q/", &["one two"]), ["one aoneQ"]);
+    }
+}
@@ -4,6 +4,8 @@ mod op;
 mod cmd;
 mod parse;
 mod exec;
+mod reg;
+mod settings;
 
 extern crate clap;
 use clap::{Arg, App};
@@ -18,20 +20,52 @@ fn main() {
                .short("e")
                .long("explain")
                .help("Explains the input command rather than executing it"))
+          .arg(Arg::with_name("CarryRegisters")
+               .long("carry-registers")
+               .help("Carries register contents over from each line to the next, in input order"))
+          .arg(Arg::with_name("Buffer")
+               .short("b")
+               .long("buffer")
+               .help("Runs the command once over the whole input as a single buffer instead of once per line"))
+          .arg(Arg::with_name("EscInserts")
+               .long("esc-inserts")
+               .help("Ends inserted text at <Esc> or the end of the command, as in Vim, instead of at a delimiter"))
+          .arg(Arg::with_name("Set")
+               .long("set")
+               .value_name("OPTION=VALUE")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .help("Sets a Vim option, such as tabstop=4; may be given more than once"))
           .arg(Arg::with_name("COMMAND")
                .help("The command pattern")
                .required(true))
           .get_matches();
 
+    let mut settings = settings::Settings {
+        carry_registers : matches.is_present("CarryRegisters"),
+        buffer          : matches.is_present("Buffer"),
+        esc_inserts     : matches.is_present("EscInserts"),
+        ..Default::default()
+    };
+
+    for opt in matches.values_of("Set").into_iter().flatten() {
+        if let Err(e) = settings.set(opt) {
+            eprintln!("norm: {}", e);
+            std::process::exit(1);
+        }
+    }
+
     let s = matches.value_of("COMMAND").unwrap().to_owned();
-    let mut cmd = cmd::Cmd::new(&s);
+    let mut cmd = cmd::Cmd{ esc_inserts : settings.esc_inserts, ..cmd::Cmd::new(&s) };
 
-    let ops = parse::parse_command(&mut cmd);
+    /* only a quiet command fails without ending the program */
+    let ops = parse::parse_command(&mut cmd).unwrap();
 
     if matches.is_present("Explain") {
         println!("I can explain...");
         for op in ops    { op.explain(); }
     } else {
-        exec::execute(ops);
+        exec::execute(ops, settings);
     }
 }
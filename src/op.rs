@@ -3,38 +3,160 @@
  * motions and operations that comprise a norm command
  */
 
+use crate::cmd::show_keys;
+
+use regex::Regex;
+
+/*
+ * Where a search leaves the cursor relative to the match: some characters
+ * from its start or its end, or some lines below or above it.
+ */
 #[derive(Copy, Clone, Debug)]
+pub enum SearchOffset {
+    Start(i32),
+    End(i32),
+    Line(i32),
+}
+
+#[derive(Clone, Debug)]
 pub enum SingleMotion {
     Beg,
     End,
+    FirstNonBlank,
+    LastNonBlank,
+    Column { n : u32 },
+    Match,
+    Percent { n : u32 },
     Left,
     Right,
     Word,
+    BigWord,
     Back,
+    BigBack,
+    WordEnd,
+    BigWordEnd,
+    WordEndBack,
+    BigWordEndBack,
     Till     { c : char },
     BackTill { c : char },
     Find     { c : char },
     BackFind { c : char },
-    FindNext,
+    FindNext { reverse : bool },
+    Down,
+    Up,
+    GotoLine { n : u32 },
+    Mark     { c : char },
+    MarkLine { c : char },
+    Search     { re : Option<Regex>, backward : bool, offset : SearchOffset },
+    SearchNext { reverse : bool },
+    SearchWord { backward : bool },
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct Motion {
     pub mot    : SingleMotion,
     pub repeat : u32,
 }
 
+#[derive(Copy, Clone, Debug)]
+pub enum TextObject {
+    Word    { inner : bool },
+    BigWord { inner : bool },
+    Quote   { c : char, inner : bool },
+    Block   { open : char, close : char, inner : bool },
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Object {
+    pub obj    : TextObject,
+    pub repeat : u32,
+}
+
+#[derive(Clone, Debug)]
+pub enum Target {
+    Motion(Motion),
+    Object(Object),
+    Line(u32),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum CaseOp {
+    Toggle,
+    Lower,
+    Upper,
+}
+
+/*
+ * Flags of :s. `keep` (&) reuses the flags of the last substitute, and
+ * `ignore_case` is only set by i or I.
+ */
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SubFlags {
+    pub keep        : bool,
+    pub global      : bool,
+    pub ignore_case : Option<bool>,
+}
+
 #[derive(Clone, Debug)]
 pub enum Op {
     Motion(Motion),
-    Insert { s : String },
-    Append { s : String },
-    LineAppend { s : String },
-    Delete { motion : Motion },
-    DeleteToEnd,
-    Yank { motion : Motion },
-    Put,
-    Repeat,
+    Insert { s : String, n : u32 },
+    Append { s : String, n : u32 },
+    LineAppend { s : String, n : u32 },
+    LineInsert { s : String, n : u32 },
+    StartInsert { s : String, n : u32 },
+    LastInsert { s : String, n : u32 },
+    Delete { target : Target, reg : Option<char> },
+    DeleteToEnd { reg : Option<char>, n : u32 },
+    Change { target : Target, s : String, reg : Option<char> },
+    Yank { target : Target, reg : Option<char> },
+    Put { reg : Option<char>, n : u32, past : bool },
+    PutBefore { reg : Option<char>, n : u32, past : bool },
+    Replace { c : char, n : u32 },
+    Overwrite { s : String, n : u32 },
+    ToggleCase { n : u32 },
+    Case { target : Target, kind : CaseOp },
+    Shift { target : Target, left : bool },
+    Format { target : Target, keep : bool },
+    OpenBelow { s : String, n : u32 },
+    OpenAbove { s : String, n : u32 },
+    Join { n : u32, spaces : bool },
+    Substitute { re : Option<Regex>, rep : String, flags : SubFlags, all : bool, n : u32 },
+    SubRepeat { all : bool, n : u32 },
+    Record { reg : char, keys : String },
+    Play { reg : char, n : u32 },
+    Repeat { n : Option<u32> },
+    Undo { n : u32 },
+    Redo { n : u32 },
+    UndoLine,
+    Mark { c : char },
+    AddNumber { n : u32, sub : bool },
+    Resume { s : String, replace : bool },
+}
+
+impl SearchOffset {
+    pub fn explain(&self) {
+        match self {
+            SearchOffset::Start(0) => {},
+            SearchOffset::Start(n) => print!(", then {} characters from its start", n),
+            SearchOffset::End(0)   => print!(", landing on its last character"),
+            SearchOffset::End(n)   => print!(", then {} characters from its last character", n),
+            SearchOffset::Line(n)  => print!(", then {} lines from it, linewise", n),
+        }
+    }
+}
+
+fn explain_search(re : &Option<Regex>, backward : bool, offset : &SearchOffset, repeat : u32) {
+    let dir = if backward { "backward" } else { "forward" };
+    let pat = match re {
+        Some(re) => format!("/{}/", re.as_str()),
+        None     => "the last search pattern".to_string(),
+    };
+    match repeat {
+        1 => print!("{} to the next match of {}", dir, pat),
+        _ => print!("{} {} matches of {}", dir, repeat, pat),
+    }
+    offset.explain();
 }
 
 impl SingleMotion {
@@ -43,52 +165,333 @@ impl SingleMotion {
             match self {
                 SingleMotion::Beg            => print!("to beginning of line"),
                 SingleMotion::End            => print!("to end of line"),
+                SingleMotion::FirstNonBlank  => print!("to the first non-blank character of the line"),
+                SingleMotion::LastNonBlank   => print!("to the last non-blank character of the line"),
+                SingleMotion::Column { n }   => print!("to display column {}", n),
+                SingleMotion::Match          => print!("to the partner of the next bracket or paired word"),
+                SingleMotion::Percent { n }  => print!("to the line {}% of the way through", n),
                 SingleMotion::Left           => print!("left"),
                 SingleMotion::Right          => print!("right"),
                 SingleMotion::Word           => print!("forward word"),
+                SingleMotion::BigWord        => print!("forward WORD"),
                 SingleMotion::Back           => print!("backward word"),
+                SingleMotion::BigBack        => print!("backward WORD"),
+                SingleMotion::WordEnd        => print!("to end of word"),
+                SingleMotion::BigWordEnd     => print!("to end of WORD"),
+                SingleMotion::WordEndBack    => print!("backward to end of word"),
+                SingleMotion::BigWordEndBack => print!("backward to end of WORD"),
                 SingleMotion::Till     { c } => print!("forward until character '{}'", c),
                 SingleMotion::BackTill { c } => print!("backward until character '{}'", c),
                 SingleMotion::Find     { c } => print!("forward to character '{}'", c),
                 SingleMotion::BackFind { c } => print!("backward to character '{}'", c),
-                SingleMotion::FindNext       => print!("to next occurance of target from previous 't/T' or 'f/F' command"),
+                SingleMotion::FindNext { reverse : false } => print!("to next occurance of target from previous 't/T' or 'f/F' command"),
+                SingleMotion::FindNext { reverse : true }  => print!("to next occurance of target from previous 't/T' or 'f/F' command, in the opposite direction"),
+                SingleMotion::Down           => print!("down a line"),
+                SingleMotion::Up             => print!("up a line"),
+                SingleMotion::GotoLine { n } => match n {
+                    0 => print!("to the last line"),
+                    _ => print!("to line {}", n),
+                },
+                SingleMotion::Mark     { c } => print!("to mark '{}'", c),
+                SingleMotion::MarkLine { c } => print!("to the line of mark '{}'", c),
+                SingleMotion::Search { re, backward, offset } => explain_search(re, *backward, offset, 1),
+                SingleMotion::SearchNext { reverse } => match reverse {
+                    false => print!("to the next match of the last search"),
+                    true  => print!("to the next match of the last search, in the opposite direction"),
+                },
+                SingleMotion::SearchWord { backward } => match backward {
+                    false => print!("forward to the next occurance of the word under the cursor"),
+                    true  => print!("backward to the previous occurance of the word under the cursor"),
+                },
             }
         } else {
             match self {
                 SingleMotion::Beg            => print!("to beginning of line"),
-                SingleMotion::End            => print!("to end of line"),
+                SingleMotion::End            => print!("to end of line {} lines down", repeat - 1),
+                SingleMotion::FirstNonBlank  => print!("to the first non-blank character of the line"),
+                SingleMotion::LastNonBlank   => print!("to the last non-blank character {} lines down", repeat - 1),
+                SingleMotion::Column { n }   => print!("to display column {}", n),
+                SingleMotion::Match          => print!("to the partner of the next bracket or paired word"),
+                SingleMotion::Percent { n }  => print!("to the line {}% of the way through", n),
                 SingleMotion::Left           => print!("left {}", repeat),
                 SingleMotion::Right          => print!("right {}", repeat),
                 SingleMotion::Word           => print!("forward {} words", repeat),
+                SingleMotion::BigWord        => print!("forward {} WORDs", repeat),
                 SingleMotion::Back           => print!("backward {} words", repeat),
+                SingleMotion::BigBack        => print!("backward {} WORDs", repeat),
+                SingleMotion::WordEnd        => print!("to end of {} words", repeat),
+                SingleMotion::BigWordEnd     => print!("to end of {} WORDs", repeat),
+                SingleMotion::WordEndBack    => print!("backward to end of {} words", repeat),
+                SingleMotion::BigWordEndBack => print!("backward to end of {} WORDs", repeat),
                 SingleMotion::Till     { c } => print!("forward until {} occurances of character '{}'", repeat, c),
                 SingleMotion::BackTill { c } => print!("backward until {} occurances of character '{}'", repeat, c),
                 SingleMotion::Find     { c } => print!("forward to {} occurances of character '{}'", repeat, c),
                 SingleMotion::BackFind { c } => print!("backward to {} occurances of character '{}'", repeat, c),
-                SingleMotion::FindNext       => print!("to {} next occurances of target from previous 't' or 'f' command ", repeat),
+                SingleMotion::FindNext { reverse : false } => print!("to {} next occurances of target from previous 't/T' or 'f/F' command", repeat),
+                SingleMotion::FindNext { reverse : true }  => print!("to {} next occurances of target from previous 't/T' or 'f/F' command, in the opposite direction", repeat),
+                SingleMotion::Down           => print!("down {} lines", repeat),
+                SingleMotion::Up             => print!("up {} lines", repeat),
+                SingleMotion::GotoLine { n } => match n {
+                    0 => print!("to the last line"),
+                    _ => print!("to line {}", n),
+                },
+                SingleMotion::Mark     { c } => print!("to mark '{}'", c),
+                SingleMotion::MarkLine { c } => print!("to the line of mark '{}'", c),
+                SingleMotion::Search { re, backward, offset } => explain_search(re, *backward, offset, repeat),
+                SingleMotion::SearchNext { reverse } => match reverse {
+                    false => print!("{} matches on with the last search", repeat),
+                    true  => print!("{} matches on with the last search, in the opposite direction", repeat),
+                },
+                SingleMotion::SearchWord { backward } => match backward {
+                    false => print!("forward {} occurances of the word under the cursor", repeat),
+                    true  => print!("backward {} occurances of the word under the cursor", repeat),
+                },
             }
         }
     }
 }
 
 impl Motion {
+    /*
+     * Linewise motions make an operator act on whole lines.
+     */
+    pub fn is_linewise(&self) -> bool {
+        matches!(self.mot, SingleMotion::Down | SingleMotion::Up | SingleMotion::GotoLine { .. }
+                         | SingleMotion::Percent { .. } | SingleMotion::MarkLine { .. })
+    }
+
+    /*
+     * Vim puts the text deleted by these motions in "1 even when it's less
+     * than a line.
+     */
+    pub fn uses_reg_one(&self) -> bool {
+        matches!(self.mot, SingleMotion::Search { .. } | SingleMotion::SearchNext { .. }
+                         | SingleMotion::Match | SingleMotion::Mark { .. })
+    }
+
     pub fn explain(&self) {
         self.mot.explain(self.repeat);
     }
+
+    /* motions that jump to a line or column keep their count there */
+    pub fn set_count(&mut self, count : u32) {
+        match &mut self.mot {
+              SingleMotion::Column   { n }
+            | SingleMotion::Percent  { n }
+            | SingleMotion::GotoLine { n } => *n = count,
+            _                              => self.repeat = count,
+        }
+    }
+}
+
+impl TextObject {
+    pub fn explain(&self, repeat : u32) {
+        let which = |inner : &bool| if *inner { "inside" } else { "around" };
+        if repeat == 1 {
+            match self {
+                TextObject::Word    { inner }              => print!("{} word", which(inner)),
+                TextObject::BigWord { inner }              => print!("{} WORD", which(inner)),
+                TextObject::Quote   { c, inner }           => print!("{} {}-quoted string", which(inner), c),
+                TextObject::Block   { open, close, inner } => print!("{} {}{} block", which(inner), open, close),
+            }
+        } else {
+            match self {
+                TextObject::Word    { inner }              => print!("{} {} words", which(inner), repeat),
+                TextObject::BigWord { inner }              => print!("{} {} WORDs", which(inner), repeat),
+                TextObject::Quote   { c, inner }           => print!("around {}-quoted string{}", c,
+                                                                     if *inner { ", excluding whitespace" } else { "" }),
+                TextObject::Block   { open, close, inner } => print!("{} {}{} block {} levels out", which(inner), open, close, repeat),
+            }
+        }
+    }
+}
+
+impl Object {
+    pub fn explain(&self) {
+        self.obj.explain(self.repeat);
+    }
+}
+
+impl Target {
+    pub fn set_count(&mut self, count : u32) {
+        match self {
+            Target::Motion(m) => m.set_count(count),
+            Target::Object(o) => o.repeat = count,
+            Target::Line(n)   => *n = count,
+        }
+    }
+
+    pub fn uses_reg_one(&self) -> bool {
+        match self {
+            Target::Motion(m) => m.uses_reg_one(),
+            _                 => false,
+        }
+    }
+
+    pub fn explain(&self) {
+        match self {
+            Target::Motion(m) => m.explain(),
+            Target::Object(o) => o.explain(),
+            Target::Line(1)   => print!("the whole line"),
+            Target::Line(n)   => print!("{} whole lines", n),
+        }
+    }
+}
+
+fn explain_reg(reg : &Option<char>) {
+    if let Some(r) = reg    { print!(" using register '{}'", r); }
+}
+
+fn explain_times(n : &u32) {
+    if *n > 1    { print!(" {} times", n); }
+}
+
+fn explain_past(past : bool) {
+    if past      { print!(", leaving the cursor just after it"); }
 }
 
 impl Op {
     pub fn explain(&self) {
         match self {
             Op::Motion(motion)   => { print!("- go "); motion.explain(); println!(); },
-            Op::Insert{ s }      =>   println!("- insert '{}' at the current cursor location", s),
-            Op::Append{ s }      =>   println!("- append '{}' after the current cursor location", s),
-            Op::LineAppend{ s }  =>   println!("- append '{}' at the end of the line", s),
-            Op::Delete{ motion } => { print!("- delete "); motion.explain(); println!(); },
-            Op::DeleteToEnd      =>   println!("- delete from current cursor position to the end of the line"),
-            Op::Yank{ motion }   => { print!("- yank "); motion.explain(); println!(); },
-            Op::Put              =>   println!("- put yanked text at the current cursor location"),
-            Op::Repeat           =>   println!("- repeat last non-motion action"),
+            Op::Insert{ s, n }   => {
+                print!("- insert '{}'", show_keys(s)); explain_times(n); println!(" at the current cursor location");
+            },
+            Op::Append{ s, n }   => {
+                print!("- append '{}'", show_keys(s)); explain_times(n); println!(" after the current cursor location");
+            },
+            Op::LineAppend{ s, n } => {
+                print!("- append '{}'", show_keys(s)); explain_times(n); println!(" at the end of the line");
+            },
+            Op::LineInsert{ s, n } => {
+                print!("- insert '{}'", show_keys(s)); explain_times(n); println!(" before the first non-blank character of the line");
+            },
+            Op::StartInsert{ s, n } => {
+                print!("- insert '{}'", show_keys(s)); explain_times(n); println!(" at the start of the line");
+            },
+            Op::LastInsert{ s, n } => {
+                print!("- insert '{}'", show_keys(s)); explain_times(n); println!(" where the last insert stopped");
+            },
+            Op::Delete{ target, reg } => {
+                print!("- delete "); target.explain(); explain_reg(reg); println!();
+            },
+            Op::DeleteToEnd{ reg, n } => {
+                match n {
+                    1 => print!("- delete from current cursor position to the end of the line"),
+                    _ => print!("- delete from current cursor position to the end of the line {} lines down", n - 1),
+                }
+                explain_reg(reg); println!();
+            },
+            Op::Change{ target, s, reg } => {
+                print!("- change "); target.explain(); explain_reg(reg); println!(", inserting '{}'", show_keys(s));
+            },
+            Op::Yank{ target, reg } => {
+                print!("- yank "); target.explain(); explain_reg(reg); println!();
+            },
+            Op::Put{ reg, n, past } => {
+                print!("- put text"); explain_times(n); print!(" after the current cursor location"); explain_reg(reg); explain_past(*past); println!();
+            },
+            Op::PutBefore{ reg, n, past } => {
+                print!("- put text"); explain_times(n); print!(" before the current cursor location"); explain_reg(reg); explain_past(*past); println!();
+            },
+            Op::Replace{ c, n }  => match n {
+                1 =>   println!("- replace the character under the cursor with '{}'", c),
+                _ =>   println!("- replace {} characters starting at the cursor with '{}'", n, c),
+            },
+            Op::Overwrite{ s, n } => match n {
+                1 =>   println!("- overwrite text starting at the cursor with '{}'", show_keys(s)),
+                _ =>   println!("- overwrite text starting at the cursor with '{}' {} times", show_keys(s), n),
+            },
+            Op::ToggleCase{ n }  => match n {
+                1 =>   println!("- switch the case of the character under the cursor and move right"),
+                _ =>   println!("- switch the case of {} characters and move right past them", n),
+            },
+            Op::Case{ target, kind } => {
+                match kind {
+                    CaseOp::Toggle => print!("- switch the case of text "),
+                    CaseOp::Lower  => print!("- make lowercase text "),
+                    CaseOp::Upper  => print!("- make uppercase text "),
+                }
+                target.explain(); println!();
+            },
+            Op::Shift{ target, left } => {
+                if *left { print!("- shift left text "); } else { print!("- shift right text "); }
+                target.explain(); println!();
+            },
+            Op::Format{ target, keep } => {
+                print!("- format text "); target.explain();
+                if *keep { print!(", keeping the cursor on the same text"); }
+                println!();
+            },
+            Op::OpenBelow{ s, n } => match n {
+                1 =>   println!("- open a new line below the current one containing '{}'", show_keys(s)),
+                _ =>   println!("- open {} new lines below the current one, each containing '{}'", n, show_keys(s)),
+            },
+            Op::OpenAbove{ s, n } => match n {
+                1 =>   println!("- open a new line above the current one containing '{}'", show_keys(s)),
+                _ =>   println!("- open {} new lines above the current one, each containing '{}'", n, show_keys(s)),
+            },
+            Op::Join{ n, spaces } => {
+                let n = if *n < 2 { 2 } else { *n };
+                if *spaces { println!("- join {} lines, separating them with a space", n); }
+                else       { println!("- join {} lines as they are", n); }
+            },
+            Op::Substitute{ re, rep, flags, all, n } => {
+                match re {
+                    Some(re) => print!("- substitute /{}/", re.as_str()),
+                    None     => print!("- substitute the last search pattern"),
+                }
+                print!(" with '{}'", rep);
+                match (all, n) {
+                    (true, _)  => print!(" on every line"),
+                    (false, 1) => print!(" on the current line"),
+                    (false, n) => print!(" on {} lines starting at the current one", n),
+                }
+                if flags.global    { print!(", every match"); }
+                match flags.ignore_case {
+                    Some(true)  => print!(", ignoring case"),
+                    Some(false) => print!(", matching case"),
+                    None        => {},
+                }
+                if flags.keep      { print!(", with the flags of the last substitute"); }
+                println!();
+            },
+            Op::SubRepeat{ all, n } => match (all, n) {
+                (false, 1) =>   println!("- repeat the last substitute on the current line, without its flags"),
+                (false, n) =>   println!("- repeat the last substitute on {} lines starting at the current one, without its flags", n),
+                (true, _)  =>   println!("- repeat the last substitute with its flags and the last search pattern on every line"),
+            },
+            Op::Record{ reg, keys } =>   println!("- store the commands recorded since 'q{}', '{}', in register '{}'", reg, show_keys(keys), reg),
+            Op::Play{ reg, n }   => {
+                match reg {
+                    '@' => print!("- play the register played last"),
+                    _   => print!("- play the commands in register '{}'", reg),
+                }
+                if *n > 1    { print!(" {} times", n); }
+                println!(", stopping if a motion fails");
+            },
+            Op::Repeat{ n }      => match n {
+                None    =>   println!("- repeat last non-motion action"),
+                Some(n) =>   println!("- repeat last non-motion action with a count of {}", n),
+            },
+            Op::Undo{ n }        => match n {
+                1 =>   println!("- undo the last change"),
+                _ =>   println!("- undo the last {} changes", n),
+            },
+            Op::Redo{ n }        => match n {
+                1 =>   println!("- redo the last undone change"),
+                _ =>   println!("- redo the last {} undone changes", n),
+            },
+            Op::UndoLine         =>   println!("- undo all latest changes on the last changed line"),
+            Op::Mark{ c }        =>   println!("- set mark '{}' at the cursor position", c),
+            Op::Resume{ s, replace } => match replace {
+                false =>   println!("- go back to inserting '{}'", show_keys(s)),
+                true  =>   println!("- go back to overwriting text with '{}'", show_keys(s)),
+            },
+            Op::AddNumber{ n, sub } => match sub {
+                false =>   println!("- add {} to the number at or after the cursor", n),
+                true  =>   println!("- subtract {} from the number at or after the cursor", n),
+            },
         }
     }
 
@@ -101,23 +504,82 @@ impl Op {
 
     pub fn get_s(&self) -> &String {
         match self {
-              Op::Insert     { s }
-            | Op::Append     { s }
-            | Op::LineAppend { s } => s,
-            _                      => panic!("get_s on non-insert op")
+              Op::Insert     { s, .. }
+            | Op::Append     { s, .. }
+            | Op::LineAppend { s, .. }
+            | Op::LineInsert { s, .. }
+            | Op::StartInsert{ s, .. }
+            | Op::LastInsert { s, .. }
+            | Op::OpenBelow  { s, .. }
+            | Op::OpenAbove  { s, .. } => s,
+            _                          => panic!("get_s on non-insert op")
         }
     }
 
-    pub fn get_repeat(&self) -> u32 { self.get_motion().repeat }
-    pub fn get_c(&self) -> char {
-        let m = self.get_motion();
-
-        match m.mot {
-              SingleMotion::Till     { c }
-            | SingleMotion::BackTill { c }
-            | SingleMotion::Find     { c }
-            | SingleMotion::BackFind { c } => c,
-            _                              => panic!("get_c() on simple motion")
+    pub fn get_n(&self) -> usize {
+        match self {
+              Op::Insert     { n, .. }
+            | Op::Append     { n, .. }
+            | Op::LineAppend { n, .. }
+            | Op::LineInsert { n, .. }
+            | Op::StartInsert{ n, .. }
+            | Op::LastInsert { n, .. }
+            | Op::OpenBelow  { n, .. }
+            | Op::OpenAbove  { n, .. } => *n as usize,
+            _                          => panic!("get_n on non-insert op")
         }
     }
+
+    /*
+     * A count given to '.' replaces the count of the op it repeats.
+     */
+    pub fn set_count(&mut self, count : u32) {
+        match self {
+              Op::Insert      { n, .. }
+            | Op::Append      { n, .. }
+            | Op::LineAppend  { n, .. }
+            | Op::LineInsert  { n, .. }
+            | Op::StartInsert { n, .. }
+            | Op::LastInsert  { n, .. }
+            | Op::DeleteToEnd { n, .. }
+            | Op::Put         { n, .. }
+            | Op::PutBefore   { n, .. }
+            | Op::Replace     { n, .. }
+            | Op::Overwrite   { n, .. }
+            | Op::ToggleCase  { n }
+            | Op::OpenBelow   { n, .. }
+            | Op::OpenAbove   { n, .. }
+            | Op::Join        { n, .. }
+            | Op::Substitute  { n, .. }
+            | Op::SubRepeat   { n, .. }
+            | Op::Play        { n, .. }
+            | Op::Undo        { n }
+            | Op::Redo        { n }
+            | Op::AddNumber   { n, .. }  => *n = count,
+              Op::Delete      { target, .. }
+            | Op::Change      { target, .. }
+            | Op::Yank        { target, .. }
+            | Op::Case        { target, .. }
+            | Op::Shift       { target, .. }
+            | Op::Format      { target, .. } => target.set_count(count),
+              Op::Motion(m)              => m.set_count(count),
+              Op::Record      { .. }
+            | Op::Repeat      { .. }
+            | Op::UndoLine
+            | Op::Mark        { .. }
+            | Op::Resume      { .. }     => {},
+        }
+    }
+
+    pub fn get_repeat(&self) -> u32 { self.get_motion().repeat }
+
+    /*
+     * Ops that may change the buffer, each of which makes an undo step.
+     * Undo and redo move through those steps instead, and a played
+     * register makes one step of everything it changes.
+     */
+    pub fn is_change(&self) -> bool {
+        !matches!(self, Op::Motion(_) | Op::Yank { .. } | Op::Record { .. } | Op::Undo { .. } | Op::Redo { .. }
+                      | Op::Mark { .. })
+    }
 }
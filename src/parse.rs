@@ -5,142 +5,922 @@
 
 use crate::op::*;
 use crate::cmd::*;
+use crate::reg::Registers;
 
-pub fn parse_command(cmd : &mut Cmd) -> Vec<Op> {
+extern crate regex;
+use regex::Regex;
+
+/*
+ * Parses a whole command. A quiet command with an error in it gives back
+ * None; any other error ends the program.
+ */
+pub fn parse_command(cmd : &mut Cmd) -> Option<Vec<Op>> {
     let mut ret = Vec::new();
 
-    while cmd.len() > 0 {
-        let mut n_s = String::new();
+    /*
+     * q{reg} starts recording and the next q stops it. The commands in
+     * between run as usual; their text is stored when recording stops.
+     */
+    let mut recording : Option<(char, usize)> = None;
+
+    while !cmd.is_empty() {
+        /* <Esc> in normal mode does nothing */
+        if cmd.first() == '\x1b' {
+            cmd.consume();
+            continue;
+        }
 
-        let c = cmd.first();
-        if c.is_numeric() && c != '0' {
-            while cmd.first().is_numeric() {
-                n_s.push(cmd.consume());
+        if cmd.first() == 'q' {
+            cmd.consume();
+            match recording.take() {
+                Some((reg, start)) => ret.push(record(cmd, reg, start, cmd.consumed - 1)),
+                None               => {
+                    let r = cmd.take()?;
+                    if !r.is_ascii_alphanumeric() && r != '"' {
+                        cmd.err("invalid register name to record into");
+                        return None;
+                    }
+                    recording = Some((r, cmd.consumed));
+                },
             }
+            continue;
         }
 
-        let quant = if n_s.len() > 0 { n_s.parse::<i32>().unwrap() } else { 1 };
+        /* after the command <C-o> runs, the text it stopped goes on */
+        let resume = cmd.resume.take().map(|delim| {
+            (delim, matches!(ret.last(), Some(Op::Overwrite { .. } | Op::Resume { replace : true, .. })))
+        });
+
+        let mut count = parse_count(cmd);
+
+        /* "x register prefix, which may itself be followed by a count */
+        let mut reg = None;
+        if cmd.first() == '"' {
+            cmd.consume();
+            let r = cmd.take()?;
+            if !Registers::is_valid_name(r) {
+                cmd.err("invalid register name");
+                return None;
+            }
+            reg = Some(r);
+
+            count = mul_count(count, parse_count(cmd));
+        }
 
-        let o =
-            if let Some(motion) = parse_motion(cmd) {
+        let mut o =
+            if let Some(motion) = parse_motion(cmd, count) {
                 Op::Motion(motion)
             } else if let Some(parser) = get_cmd_parser(cmd.first()) {
-                parser(cmd)
+                parser(cmd, count)?
             } else {
-                let msg = String::from("unknown op");
-                cmd.err(&msg);
-                unreachable!()
+                cmd.err("unknown op");
+                return None;
             };
 
-        for _ in 0..quant    { ret.push(o.clone()); }
+        if let Some(r) = reg {
+            match o {
+                  Op::Delete      { reg : ref mut op_reg, .. }
+                | Op::DeleteToEnd { reg : ref mut op_reg, .. }
+                | Op::Change      { reg : ref mut op_reg, .. }
+                | Op::Yank        { reg : ref mut op_reg, .. }
+                | Op::Put         { reg : ref mut op_reg, .. }
+                | Op::PutBefore   { reg : ref mut op_reg, .. } => *op_reg = Some(r),
+                _                                              => {
+                    cmd.err("this op doesn't take a register");
+                    return None;
+                },
+            }
+        }
+
+        ret.push(o);
+
+        if let Some((delim, replace)) = resume {
+            ret.push(Op::Resume{ s : parse_text_to(cmd, delim)?, replace });
+        }
+    }
+
+    if let Some((reg, start)) = recording {
+        ret.push(record(cmd, reg, start, cmd.consumed));
     }
 
-    ret
+    Some(ret)
+}
+
+fn record(cmd : &Cmd, reg : char, start : usize, end : usize) -> Op {
+    let keys = cmd.orig.chars().skip(start).take(end - start).collect();
+    Op::Record{ reg, keys }
+}
+
+fn parse_count(cmd : &mut Cmd) -> Option<u32> {
+    let c = cmd.peek(0)?;
+    if !c.is_ascii_digit() || c == '0'    { return None; }
+
+    let mut n_s = String::new();
+    while cmd.peek(0).is_some_and(|c| c.is_ascii_digit())    { n_s.push(cmd.consume()); }
+    Some(n_s.parse::<u32>().unwrap_or(u32::MAX))
+}
+
+/*
+ * Counts before an operator and before its motion multiply, so "2d3w"
+ * deletes six words.
+ */
+fn mul_count(a : Option<u32>, b : Option<u32>) -> Option<u32> {
+    match (a, b) {
+        (None, None) => None,
+        (a, b)       => Some(a.unwrap_or(1).saturating_mul(b.unwrap_or(1))),
+    }
 }
 
-fn get_cmd_parser(c : char) -> Option<fn(&mut Cmd) -> Op> {
+/* parses the op starting at the current character, giving back None after an error */
+type CmdParser = fn(&mut Cmd, Option<u32>) -> Option<Op>;
+
+fn get_cmd_parser(c : char) -> Option<CmdParser> {
     match c {
         'i' => Some(parse_Insert),
         'a' => Some(parse_Append),
         'A' => Some(parse_LineAppend),
+        'I' => Some(parse_LineInsert),
         'd' => Some(parse_Delete),
         'D' => Some(parse_DeleteToEnd),
+        'x' => Some(parse_DeleteChar),
+        'X' => Some(parse_DeleteBack),
+        'c' => Some(parse_Change),
+        'C' => Some(parse_ChangeToEnd),
+        's' => Some(parse_Substitute),
+        'S' => Some(parse_SubstituteLine),
         'y' => Some(parse_Yank),
         'p' => Some(parse_Put),
+        'P' => Some(parse_PutBefore),
+        'r' => Some(parse_Replace),
+        'R' => Some(parse_Overwrite),
+        '~' => Some(parse_ToggleCase),
+        '>' => Some(parse_Shift),
+        '<' => Some(parse_Shift),
+        'g' => Some(parse_g),
+        'o' => Some(parse_OpenBelow),
+        'O' => Some(parse_OpenAbove),
+        'J' => Some(parse_Join),
         '.' => Some(parse_Repeat),
+        ':' => Some(parse_Ex),
+        '&' => Some(parse_SubRepeat),
+        '@' => Some(parse_Play),
+        'm' => Some(parse_Mark),
+        'u' => Some(parse_Undo),
+        'U' => Some(parse_UndoLine),
+        '\x12' => Some(parse_Redo),
+        '\x01' => Some(parse_Increment),
+        '\x18' => Some(parse_Decrement),
          _  => None
     }
 }
 
-fn parse_Insert(cmd : &mut Cmd) -> Op {
-    cmd.consume();
+/*
+ * Insert text runs from the character after the op up to the next
+ * occurrence of that same character, which acts as the delimiter. With
+ * esc_inserts it runs up to <Esc> or the end of the command instead.
+ */
+fn parse_text(cmd : &mut Cmd) -> Option<String> {
+    let delim = if cmd.esc_inserts { None } else { Some(cmd.take()?) };
+    parse_text_to(cmd, delim)
+}
+
+/*
+ * Reads text up to `delim`, or to <Esc> or the end of the command with
+ * none. A register name after <C-r> is taken as it is, and <C-o> stops
+ * the text for a command; see parse_command().
+ */
+fn parse_text_to(cmd : &mut Cmd, delim : Option<char>) -> Option<String> {
     let mut s = String::new();
-    let delim = cmd.consume();
-    let mut c : char;
-    while { c = cmd.consume(); c != delim }   { s.push(c); }
-    Op::Insert{ s : s }
+    while delim.is_some() || !cmd.is_empty() {
+        let c = cmd.take()?;
+        if Some(c) == delim || (delim.is_none() && c == '\x1b')    { break; }
+        s.push(c);
+
+        match c {
+            '\x12' => {
+                let r = cmd.take()?;
+                s.push(r);
+                if matches!(r, '\x12' | '\x0f' | '\x10')    { s.push(cmd.take()?); }
+            },
+            '\x0f' => {
+                cmd.resume = Some(delim);
+                break;
+            },
+            _      => {},
+        }
+    }
+    Some(s)
 }
 
-fn parse_Append(cmd : &mut Cmd) -> Op {
+fn parse_Insert(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
     cmd.consume();
-    let mut s = String::new();
-    let delim = cmd.consume();
-    let mut c : char;
-    while { c = cmd.consume(); c != delim }   { s.push(c); }
-    Op::Append{ s : s }
+    Some(Op::Insert{ s : parse_text(cmd)?, n : count.unwrap_or(1) })
 }
 
-fn parse_LineAppend(cmd : &mut Cmd) -> Op {
+fn parse_Append(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
     cmd.consume();
-    let mut s = String::new();
-    let delim = cmd.consume();
-    let mut c : char;
-    while { c = cmd.consume(); c != delim }   { s.push(c); }
-    Op::LineAppend{ s : s }
+    Some(Op::Append{ s : parse_text(cmd)?, n : count.unwrap_or(1) })
+}
+
+fn parse_LineAppend(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::LineAppend{ s : parse_text(cmd)?, n : count.unwrap_or(1) })
+}
+
+fn parse_LineInsert(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::LineInsert{ s : parse_text(cmd)?, n : count.unwrap_or(1) })
+}
+
+fn parse_OpenBelow(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::OpenBelow{ s : parse_text(cmd)?, n : count.unwrap_or(1) })
+}
+
+fn parse_OpenAbove(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::OpenAbove{ s : parse_text(cmd)?, n : count.unwrap_or(1) })
+}
+
+fn parse_Join(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::Join{ n : count.unwrap_or(1), spaces : true })
 }
 
-fn parse_Delete(cmd : &mut Cmd) -> Op {
+fn parse_Delete(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
     cmd.consume();
-    if let Some(m) = parse_motion(cmd) {
-        Op::Delete{ motion : m }
+    if let Some(target) = parse_target(cmd, 'd', count) {
+        Some(Op::Delete{ target, reg : None })
     } else {
-        let msg = String::from("expected motion after delete");
-        cmd.err(&msg);
-        unreachable!()
+        cmd.err("expected motion or text object after delete");
+        None
     }
 }
 
-fn parse_DeleteToEnd(cmd : &mut Cmd) -> Op { cmd.consume(); Op::DeleteToEnd }
+fn parse_DeleteToEnd(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::DeleteToEnd{ reg : None, n : count.unwrap_or(1) })
+}
+
+fn parse_Change(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    let target = if let Some(target) = parse_target(cmd, 'c', count) {
+        target
+    } else {
+        cmd.err("expected motion or text object after change");
+        return None;
+    };
+    Some(Op::Change{ target, s : parse_text(cmd)?, reg : None })
+}
+
+fn parse_ChangeToEnd(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    let target = Target::Motion(Motion{ mot : SingleMotion::End, repeat : count.unwrap_or(1) });
+    Some(Op::Change{ target, s : parse_text(cmd)?, reg : None })
+}
+
+fn parse_DeleteChar(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    let target = Target::Motion(Motion{ mot : SingleMotion::Right, repeat : count.unwrap_or(1) });
+    Some(Op::Delete{ target, reg : None })
+}
+
+fn parse_DeleteBack(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    let target = Target::Motion(Motion{ mot : SingleMotion::Left, repeat : count.unwrap_or(1) });
+    Some(Op::Delete{ target, reg : None })
+}
 
-fn parse_Yank(cmd : &mut Cmd) -> Op {
+fn parse_Substitute(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
     cmd.consume();
-    if let Some(m) = parse_motion(cmd) {
-        Op::Yank{ motion : m }
+    let target = Target::Motion(Motion{ mot : SingleMotion::Right, repeat : count.unwrap_or(1) });
+    Some(Op::Change{ target, s : parse_text(cmd)?, reg : None })
+}
+
+fn parse_SubstituteLine(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::Change{ target : Target::Line(count.unwrap_or(1)), s : parse_text(cmd)?, reg : None })
+}
+
+fn parse_Yank(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    if let Some(target) = parse_target(cmd, 'y', count) {
+        Some(Op::Yank{ target, reg : None })
     } else {
-        let msg = String::from("expected motion after yank");
-        cmd.err(&msg);
-        unreachable!()
+        cmd.err("expected motion or text object after yank");
+        None
     }
 }
 
-fn parse_Put(cmd : &mut Cmd) -> Op { cmd.consume(); Op::Put }
+fn parse_Put(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::Put{ reg : None, n : count.unwrap_or(1), past : false })
+}
+
+fn parse_PutBefore(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::PutBefore{ reg : None, n : count.unwrap_or(1), past : false })
+}
 
-fn parse_Repeat(cmd : &mut Cmd) -> Op { cmd.consume(); Op::Repeat }
+fn parse_Replace(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::Replace{ c : cmd.take()?, n : count.unwrap_or(1) })
+}
 
-fn parse_motion(cmd : &mut Cmd) -> Option<Motion> {
-    if cmd.len() == 0    { return None; }
+fn parse_Overwrite(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::Overwrite{ s : parse_text(cmd)?, n : count.unwrap_or(1) })
+}
 
-    let mut n_s = String::new();
-    let c       = cmd.first();
-    if c.is_numeric() && c != '0' {
-        while cmd.first().is_numeric()    { n_s.push(cmd.consume()); }
+fn parse_ToggleCase(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::ToggleCase{ n : count.unwrap_or(1) })
+}
+
+/*
+ * Ops spelled with a leading 'g'. Motions that start with 'g' are handled
+ * by parse_motion() and never get here.
+ */
+fn parse_g(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    match cmd.first() {
+        '~' | 'u' | 'U' => parse_Case(cmd, count),
+        'q' | 'w'       => parse_Format(cmd, count),
+        'J'             => { cmd.consume(); Some(Op::Join{ n : count.unwrap_or(1), spaces : false }) },
+        'I'             => { cmd.consume(); Some(Op::StartInsert{ s : parse_text(cmd)?, n : count.unwrap_or(1) }) },
+        'i'             => { cmd.consume(); Some(Op::LastInsert{ s : parse_text(cmd)?, n : count.unwrap_or(1) }) },
+        '&'             => { cmd.consume(); Some(Op::SubRepeat{ all : true, n : 1 }) },
+        'p'             => { cmd.consume(); Some(Op::Put{ reg : None, n : count.unwrap_or(1), past : true }) },
+        'P'             => { cmd.consume(); Some(Op::PutBefore{ reg : None, n : count.unwrap_or(1), past : true }) },
+         _              => {
+             cmd.err("unknown op after 'g'");
+             None
+         }
+    }
+}
+
+fn parse_Case(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    let c    = cmd.consume();
+    let kind = match c {
+        '~' => CaseOp::Toggle,
+        'u' => CaseOp::Lower,
+        _   => CaseOp::Upper,
+    };
+
+    /* g~~, guu and gUU, or spelled out as g~g~, gugu and gUgU */
+    let target = if cmd.peek(0) == Some('g') && cmd.peek(1) == Some(c) {
+        cmd.consume();
+        cmd.consume();
+        Target::Line(count.unwrap_or(1))
+    } else if let Some(target) = parse_target(cmd, c, count) {
+        target
+    } else {
+        cmd.err("expected motion or text object after case operator");
+        return None;
+    };
+
+    Some(Op::Case{ target, kind })
+}
+
+fn parse_Format(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    let c = cmd.consume();
+
+    /* gqq and gww, or spelled out as gqgq and gwgw */
+    let target = if cmd.peek(0) == Some('g') && cmd.peek(1) == Some(c) {
+        cmd.consume();
+        cmd.consume();
+        Target::Line(count.unwrap_or(1))
+    } else if let Some(target) = parse_target(cmd, c, count) {
+        target
+    } else {
+        cmd.err("expected motion or text object after format operator");
+        return None;
+    };
+
+    Some(Op::Format{ target, keep : c == 'w' })
+}
+
+fn parse_Shift(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    let c = cmd.consume();
+    if let Some(target) = parse_target(cmd, c, count) {
+        Some(Op::Shift{ target, left : c == '<' })
+    } else {
+        cmd.err("expected motion or text object after shift");
+        None
+    }
+}
+
+fn parse_Repeat(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::Repeat{ n : count })
+}
+
+fn parse_Play(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    let reg = cmd.take()?;
+    if reg != '@' && !Registers::is_valid_name(reg) {
+        cmd.err("invalid register name to play");
+        return None;
+    }
+    Some(Op::Play{ reg, n : count.unwrap_or(1) })
+}
+
+fn parse_Mark(cmd : &mut Cmd, _count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::Mark{ c : parse_mark_name(cmd)? })
+}
+
+fn parse_mark_name(cmd : &mut Cmd) -> Option<char> {
+    let c = cmd.take()?;
+    if !c.is_ascii_lowercase() {
+        cmd.err("invalid mark name, only a-z are supported");
+        return None;
+    }
+    Some(c)
+}
+
+fn parse_Undo(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::Undo{ n : count.unwrap_or(1) })
+}
+
+/* Ctrl-R */
+fn parse_Redo(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::Redo{ n : count.unwrap_or(1) })
+}
+
+/* Ctrl-A */
+fn parse_Increment(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::AddNumber{ n : count.unwrap_or(1), sub : false })
+}
+
+/* Ctrl-X */
+fn parse_Decrement(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::AddNumber{ n : count.unwrap_or(1), sub : true })
+}
+
+fn parse_UndoLine(cmd : &mut Cmd, _count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::UndoLine)
+}
+
+/* like Vim's, a count makes & (and :) cover that many lines */
+fn parse_SubRepeat(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    Some(Op::SubRepeat{ all : false, n : count.unwrap_or(1) })
+}
+
+/*
+ * The only Ex command is :s/pattern/replacement/flags, on the current line,
+ * on count lines from it when a count comes before the ':', or with % on
+ * every line. Flags are read for as long as they last, so a
+ * following command starting with one of them needs a '|' in between.
+ */
+fn parse_Ex(cmd : &mut Cmd, count : Option<u32>) -> Option<Op> {
+    cmd.consume();
+    let all = cmd.peek(0) == Some('%');
+    if all    { cmd.consume(); }
+
+    if cmd.is_empty() || cmd.consume() != 's' {
+        cmd.err("unknown Ex command, only :s is supported");
+        return None;
+    }
+
+    let delim = cmd.take()?;
+    if delim.is_alphanumeric() || delim == '\\' || delim == '"' || delim == '|' {
+        cmd.err("invalid delimiter for :s");
+        return None;
+    }
+
+    let pat   = parse_delimited(cmd, delim);
+    let ended = cmd.last() == Some('\r');
+    let rep   = if ended { String::new() } else { parse_delimited(cmd, delim) };
+    let ended = ended || cmd.last() == Some('\r');
+    let re    = if pat.is_empty() { None } else { Some(compile_pattern(cmd, &pat)?) };
+
+    let mut flags = SubFlags::default();
+    if ended {
+        return Some(Op::Substitute{ re, rep, flags, all, n : count.unwrap_or(1) });
+    }
+    if cmd.peek(0) == Some('&') {
+        cmd.consume();
+        flags.keep = true;
+    }
+    while let Some(c) = cmd.peek(0) {
+        match c {
+            'g' => flags.global      = !flags.global,
+            'i' => flags.ignore_case = Some(true),
+            'I' => flags.ignore_case = Some(false),
+            /* there is nobody to confirm with, so every match is taken */
+            'c' => {},
+            '|' | '\r' => { cmd.consume(); break; },
+             _        => break,
+        }
+        cmd.consume();
+    }
+
+    Some(Op::Substitute{ re, rep, flags, all, n : count.unwrap_or(1) })
+}
+
+/*
+ * An operator target is a motion, a text object, or a repeat of the
+ * operator's own character ("dd", "yy", ...) for whole lines. Any of
+ * them may be preceded by a count, which multiplies the operator's.
+ */
+fn parse_target(cmd : &mut Cmd, line_c : char, count : Option<u32>) -> Option<Target> {
+    let count = mul_count(count, parse_count(cmd));
+
+    match cmd.peek(0) {
+        Some(c) if c == line_c => { cmd.consume(); Some(Target::Line(count.unwrap_or(1))) },
+        Some('i') | Some('a')  => parse_object(cmd, count).map(Target::Object),
+        _                      => parse_motion(cmd, count).map(Target::Motion),
     }
-    let n = n_s.parse::<u32>().unwrap_or(1);
+}
+
+fn parse_object(cmd : &mut Cmd, count : Option<u32>) -> Option<Object> {
+    let inner = cmd.consume() == 'i';
+
+    let obj = match cmd.take()? {
+        'w'                   => TextObject::Word    { inner },
+        'W'                   => TextObject::BigWord { inner },
+        c @ ('"' | '\'' | '`') => TextObject::Quote   { c, inner },
+        '(' | ')' | 'b'       => TextObject::Block   { open : '(', close : ')', inner },
+        '[' | ']'             => TextObject::Block   { open : '[', close : ']', inner },
+        '{' | '}' | 'B'       => TextObject::Block   { open : '{', close : '}', inner },
+        '<' | '>'             => TextObject::Block   { open : '<', close : '>', inner },
+         _                    => {
+             cmd.err("unknown text object");
+             return None;
+         }
+    };
+
+    Some(Object{ obj, repeat : count.unwrap_or(1) })
+}
+
+fn parse_motion(cmd : &mut Cmd, count : Option<u32>) -> Option<Motion> {
+    if cmd.is_empty()    { return None; }
+
+    let n = count.unwrap_or(1);
 
     fn m(cmd : &mut Cmd, s_mot : SingleMotion, n : u32) -> Option<Motion> {
         cmd.consume();
         Some(Motion{ mot : s_mot, repeat : n})
-    };
+    }
     fn m_plus(s_mot : SingleMotion, n : u32) -> Option<Motion> {
         Some(Motion{ mot : s_mot, repeat : n})
-    };
+    }
 
     match cmd.first() {
         '0' => m(cmd, SingleMotion::Beg, n),
         '$' => m(cmd, SingleMotion::End, n),
+        '^' => m(cmd, SingleMotion::FirstNonBlank, 1),
+        '|' => m(cmd, SingleMotion::Column { n }, 1),
+        '%' => match count {
+            Some(n) => m(cmd, SingleMotion::Percent { n }, 1),
+            None    => m(cmd, SingleMotion::Match, 1),
+        },
         'h' => m(cmd, SingleMotion::Left, n),
         'l' => m(cmd, SingleMotion::Right, n),
         'w' => m(cmd, SingleMotion::Word, n),
+        'W' => m(cmd, SingleMotion::BigWord, n),
         'b' => m(cmd, SingleMotion::Back, n),
-        't' => { cmd.consume(); let c = cmd.consume();
-               m_plus(SingleMotion::Till     { c : c }, n) },
-        'T' => { cmd.consume(); let c = cmd.consume();
-               m_plus(SingleMotion::BackTill { c : c }, n) },
-        'f' => { cmd.consume(); let c = cmd.consume();
-               m_plus(SingleMotion::Find     { c : c }, n) },
-        'F' => { cmd.consume(); let c = cmd.consume();
-               m_plus(SingleMotion::BackFind { c : c }, n) },
-        ';' => m(cmd, SingleMotion::FindNext, n),
+        'B' => m(cmd, SingleMotion::BigBack, n),
+        'e' => m(cmd, SingleMotion::WordEnd, n),
+        'E' => m(cmd, SingleMotion::BigWordEnd, n),
+        't' => { cmd.consume(); let c = cmd.take()?;
+               m_plus(SingleMotion::Till     { c }, n) },
+        'T' => { cmd.consume(); let c = cmd.take()?;
+               m_plus(SingleMotion::BackTill { c }, n) },
+        'f' => { cmd.consume(); let c = cmd.take()?;
+               m_plus(SingleMotion::Find     { c }, n) },
+        'F' => { cmd.consume(); let c = cmd.take()?;
+               m_plus(SingleMotion::BackFind { c }, n) },
+        ';' => m(cmd, SingleMotion::FindNext { reverse : false }, n),
+        ',' => m(cmd, SingleMotion::FindNext { reverse : true }, n),
+        'j' => m(cmd, SingleMotion::Down, n),
+        'k' => m(cmd, SingleMotion::Up, n),
+        'G' => m(cmd, SingleMotion::GotoLine { n : count.unwrap_or(0) }, 1),
+        '`' => { cmd.consume(); let c = parse_mark_name(cmd)?;
+               m_plus(SingleMotion::Mark     { c }, 1) },
+        '\'' => { cmd.consume(); let c = parse_mark_name(cmd)?;
+               m_plus(SingleMotion::MarkLine { c }, 1) },
+        'g' if cmd.peek(1) == Some('g') => {
+               cmd.consume();
+               m(cmd, SingleMotion::GotoLine { n }, 1) },
+        'g' if cmd.peek(1) == Some('_') => {
+               cmd.consume();
+               m(cmd, SingleMotion::LastNonBlank, n) },
+        'g' if cmd.peek(1) == Some('e') => {
+               cmd.consume();
+               m(cmd, SingleMotion::WordEndBack, n) },
+        'g' if cmd.peek(1) == Some('E') => {
+               cmd.consume();
+               m(cmd, SingleMotion::BigWordEndBack, n) },
+        '/' | '?' => parse_search(cmd, n),
+        'n' => m(cmd, SingleMotion::SearchNext { reverse : false }, n),
+        'N' => m(cmd, SingleMotion::SearchNext { reverse : true }, n),
+        '*' => m(cmd, SingleMotion::SearchWord { backward : false }, n),
+        '#' => m(cmd, SingleMotion::SearchWord { backward : true }, n),
          _  => None
     }
 }
+
+/*
+ * /pattern/ and ?pattern?, where an escaped delimiter stands for itself and
+ * an empty pattern means the last one used. An offset may follow: /foo/e,
+ * /foo/+1, /foo/s-2. Unless it has a sign or ends the command, it needs a
+ * closing delimiter to tell it from the next command: /foo/e1/x. A lone
+ * e, s or b needs <CR> or the end of the command, so /foo/s/x/ searches
+ * and then runs s/x/.
+ */
+fn parse_search(cmd : &mut Cmd, n : u32) -> Option<Motion> {
+    let delim    = cmd.consume();
+    let backward = delim == '?';
+
+    let pat    = parse_delimited(cmd, delim);
+    let re     = if pat.is_empty() { None } else { Some(compile_pattern(cmd, &pat)?) };
+    let offset = match cmd.last() {
+        Some('\r') => SearchOffset::Start(0),
+        _          => parse_search_offset(cmd, delim),
+    };
+
+    Some(Motion{ mot : SingleMotion::Search { re, backward, offset }, repeat : n })
+}
+
+/*
+ * Reads up to the closing delimiter, <CR> as typed in Vim, or the end of
+ * the command. An escaped delimiter stands for itself; other escapes are
+ * kept for the caller.
+ */
+fn parse_delimited(cmd : &mut Cmd, delim : char) -> String {
+    let mut s = String::new();
+    while !cmd.is_empty() {
+        let c = cmd.consume();
+        if c == delim || c == '\r'    { break; }
+        if c == '\\' && !cmd.is_empty() {
+            let e = cmd.consume();
+            if e != delim    { s.push(c); }
+            s.push(e);
+        } else {
+            s.push(c);
+        }
+    }
+    s
+}
+
+fn parse_search_offset(cmd : &mut Cmd, delim : char) -> SearchOffset {
+    let mut i    = 0;
+    let kind     = cmd.peek(0).filter(|c| "esb".contains(*c));
+    if kind.is_some()    { i += 1; }
+    let sign     = cmd.peek(i).filter(|c| "+-".contains(*c));
+    if sign.is_some()    { i += 1; }
+    let mut n_s  = String::new();
+    while let Some(c) = cmd.peek(i).filter(|c| c.is_ascii_digit()) {
+        n_s.push(c);
+        i += 1;
+    }
+
+    if kind.is_none() && sign.is_none() && n_s.is_empty() {
+        if cmd.peek(0) == Some('\r')    { cmd.consume(); }
+        return SearchOffset::Start(0);
+    }
+    if sign.is_none() && n_s.is_empty() && !matches!(cmd.peek(i), None | Some('\r')) {
+        return SearchOffset::Start(0);
+    }
+    let closed = cmd.peek(i) == Some(delim) || cmd.peek(i) == Some('\r');
+    if !closed && sign.is_none() && cmd.peek(i).is_some() {
+        return SearchOffset::Start(0);
+    }
+    for _ in 0..i    { cmd.consume(); }
+    if closed    { cmd.consume(); }
+
+    let mut n = n_s.parse::<i32>().unwrap_or(if sign.is_some() { 1 } else { 0 });
+    if sign == Some('-')    { n = -n; }
+
+    match kind {
+        Some('e') => SearchOffset::End(n),
+        Some(_)   => SearchOffset::Start(n),
+        None      => SearchOffset::Line(n),
+    }
+}
+
+/*
+ * Patterns are Vim's magic patterns, turned into Rust regex syntax. \(,
+ * \%(, \), \|, \+, \=, \? and \{n,m} are special and their bare forms are
+ * literal, ^ and $ only anchor at the ends of a branch, \< and \> are word
+ * boundaries and \c / \C ignore or match case. The case flag goes into the
+ * pattern itself so that it wins over the i and I flags of :s.
+ */
+fn compile_pattern(cmd : &mut Cmd, pat : &str) -> Option<Regex> {
+    let chars : Vec<char> = pat.chars().collect();
+    let mut re            = String::new();
+    let mut ignore_case   = None;
+    let mut branch        = true;
+    let mut i             = 0;
+
+    while i < chars.len() {
+        let c     = chars[i];
+        let start = branch;
+        branch    = false;
+        i        += 1;
+
+        match c {
+            '\\' => {
+                let e = chars.get(i).copied();
+                i += 1;
+                match e {
+                    Some('(')             => { re.push('('); branch = true; },
+                    Some('%') if chars.get(i) == Some(&'(') => { i += 1; re.push_str("(?:"); branch = true; },
+                    Some(')')             => re.push(')'),
+                    Some('|')             => { re.push('|'); branch = true; },
+                    Some('+')             => re.push('+'),
+                    Some('=') | Some('?') => re.push('?'),
+                    Some('{')             => re.push_str(&parse_brace(cmd, &chars, &mut i)?),
+                    Some('<') | Some('>') => re.push_str("\\b"),
+                    Some('c')             => ignore_case = Some(true),
+                    Some('C')             => ignore_case = Some(false),
+                    Some('e')             => re.push_str("\\x1b"),
+                    Some(e)               => re.push_str(&class_escape(e).unwrap_or_else(|| format!("\\{}", e))),
+                    None                  => re.push_str("\\\\"),
+                }
+            },
+            '[' => match bracket_end(&chars, i) {
+                Some(end) => { re.push_str(&translate_bracket(&chars[i..end])); i = end + 1; },
+                None      => re.push_str("\\["),
+            },
+            '^' if start                                => re.push('^'),
+            '$' if i == chars.len() || chars[i..].starts_with(&['\\', '|'])
+                || chars[i..].starts_with(&['\\', ')'])  => re.push('$'),
+            '*' if start                                => re.push_str("\\*"),
+            '(' | ')' | '|' | '+' | '?' | '{' | '}' | '^' | '$' => { re.push('\\'); re.push(c); },
+            _                                           => re.push(c),
+        }
+    }
+
+    match ignore_case {
+        Some(true)  => re.insert_str(0, "(?i)"),
+        Some(false) => re.insert_str(0, "(?-i)"),
+        None        => {},
+    }
+
+    match Regex::new(&re) {
+        Ok(re) => Some(re),
+        Err(e) => {
+            cmd.err(&format!("bad pattern: {}", e));
+            None
+        }
+    }
+}
+
+/* Vim's character class escapes that Rust regex spells differently, or not at all */
+fn class_escape(e : char) -> Option<String> {
+    let class = match e {
+        'a' => "[A-Za-z]",
+        'A' => "[^A-Za-z]",
+        'l' => "[a-z]",
+        'L' => "[^a-z]",
+        'u' => "[A-Z]",
+        'U' => "[^A-Z]",
+        'x' => "[0-9A-Fa-f]",
+        'X' => "[^0-9A-Fa-f]",
+        'o' => "[0-7]",
+        'O' => "[^0-7]",
+        'h' => "[A-Za-z_]",
+        'H' => "[^A-Za-z_]",
+        _   => return None,
+    };
+    Some(class.to_owned())
+}
+
+/*
+ * \{n,m}, \{n}, \{n,}, \{,m} and \{} after the \{ at `i`, or the same
+ * starting with '-' to match as few as possible. Vim also takes \} to
+ * close it.
+ */
+fn parse_brace(cmd : &mut Cmd, chars : &[char], i : &mut usize) -> Option<String> {
+    let lazy = chars.get(*i) == Some(&'-');
+    if lazy    { *i += 1; }
+
+    let mut inner = String::new();
+    while let Some(&c) = chars.get(*i).filter(|c| c.is_ascii_digit() || **c == ',') {
+        inner.push(c);
+        *i += 1;
+    }
+    if chars.get(*i) == Some(&'\\')    { *i += 1; }
+    if chars.get(*i) != Some(&'}') {
+        cmd.err("bad pattern: unclosed \\{");
+        return None;
+    }
+    *i += 1;
+
+    let mut rep = match inner.split_once(',') {
+        None            if inner.is_empty() => String::from("*"),
+        None                                => format!("{{{}}}", inner),
+        Some(("", ""))                      => String::from("*"),
+        Some(("", max))                     => format!("{{0,{}}}", max),
+        Some((min, max))                    => format!("{{{},{}}}", min, max),
+    };
+    if lazy    { rep.push('?'); }
+    Some(rep)
+}
+
+/*
+ * Where the bracket expression opened just before `i` closes, if it does:
+ * a ']' first, or after '^', is part of it.
+ */
+fn bracket_end(chars : &[char], mut i : usize) -> Option<usize> {
+    if chars.get(i) == Some(&'^')    { i += 1; }
+    if chars.get(i) == Some(&']')    { i += 1; }
+    while i < chars.len() {
+        match chars[i] {
+            ']'                                          => return Some(i),
+            '\\'                                         => i += 2,
+            '[' if matches!(chars.get(i + 1), Some(':')) => {
+                i += chars[i..].windows(2).position(|w| w == [':', ']']).map_or(1, |p| p + 2);
+            },
+            _                                            => i += 1,
+        }
+    }
+    None
+}
+
+/* the inside of a bracket expression, where Rust would read '[', '&&' or '~~' as set syntax */
+fn translate_bracket(inner : &[char]) -> String {
+    let mut s = String::from("[");
+    let mut i = 0;
+    while i < inner.len() {
+        match inner[i] {
+            '\\' if i + 1 < inner.len() => {
+                if inner[i + 1] == 'e' { s.push_str("\\x1b"); } else { s.push('\\'); s.push(inner[i + 1]); }
+                i += 1;
+            },
+            '[' if inner.get(i + 1) == Some(&':') => {
+                let len = inner[i..].windows(2).position(|w| w == [':', ']']).map_or(1, |p| p + 2);
+                s.extend(&inner[i..i + len]);
+                i += len - 1;
+            },
+            c @ ('[' | '&' | '~' | '\\') => { s.push('\\'); s.push(c); },
+            c                            => s.push(c),
+        }
+        i += 1;
+    }
+    s.push(']');
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s : &str) -> Vec<Op> {
+        parse_command(&mut Cmd::new(s)).unwrap()
+    }
+
+    #[test]
+    fn search_offset_letter_needs_number_or_end() {
+        let ops = parse("/=/s/X/");
+        assert_eq!(ops.len(), 2);
+        assert!(matches!(&ops[0], Op::Motion(Motion{ mot : SingleMotion::Search{ offset : SearchOffset::Start(0), .. }, .. })));
+        assert!(matches!(&ops[1], Op::Change{ s, .. } if s == "X"));
+
+        let ops = parse("/=/e");
+        assert_eq!(ops.len(), 1);
+        assert!(matches!(&ops[0], Op::Motion(Motion{ mot : SingleMotion::Search{ offset : SearchOffset::End(0), .. }, .. })));
+
+        let ops = parse("/=/e1/x");
+        assert_eq!(ops.len(), 2);
+        assert!(matches!(&ops[0], Op::Motion(Motion{ mot : SingleMotion::Search{ offset : SearchOffset::End(1), .. }, .. })));
+    }
+
+    #[test]
+    fn quiet_errors_give_back_nothing() {
+        for s in ["dz", "f", "m!", "\"!p", "ci", "ix", ":s/\\(/", "qa@!q"] {
+            assert!(parse_command(&mut Cmd::quiet(s)).is_none(), "{}", s);
+        }
+        assert_eq!(parse_command(&mut Cmd::quiet("dwix")).map(|ops| ops.len()), None);
+        assert_eq!(parse_command(&mut Cmd::quiet("dwixx")).map(|ops| ops.len()), Some(2));
+    }
+
+    #[test]
+    fn vim_magic_patterns() {
+        let re = |p : &str| compile_pattern(&mut Cmd::new(""), p).unwrap();
+
+        assert!(re(r"\(ab\)\+c").is_match("ababc"));
+        assert!(!re(r"\(ab\)\+c").is_match("(ab)+c"));
+        assert!(re(r"(ab)+c").is_match("(ab)+c"));
+        assert!(re(r"a\{2}").is_match("baab"));
+        assert!(!re(r"a\{2}").is_match("bab"));
+        assert!(re(r"a{2}").is_match("a{2}"));
+        assert!(re(r"foo\|bar").is_match("bar"));
+        assert!(re(r"colou\=r").is_match("color"));
+        assert_eq!(re(r"a\{-1,}").find("aaa").map(|m| m.as_str()), Some("a"));
+        assert!(re(r"a$b").is_match("a$b"));
+        assert!(re(r"x^").is_match("x^"));
+        assert!(re(r"^*").is_match("*"));
+        assert!(re(r"[[(]x").is_match("(x"));
+        assert!(re(r"[[:digit:]]").is_match("7"));
+    }
+}
@@ -0,0 +1,110 @@
+/*
+ * reg.rs
+ * Vim-style registers for yanked and deleted text
+ */
+
+use std::collections::HashMap;
+
+/*
+ * Linewise text holds whole lines, each ending in '\n', and is put on
+ * lines of its own.
+ */
+#[derive(Clone, Default)]
+pub struct Register {
+    pub text     : String,
+    pub linewise : bool,
+}
+
+#[derive(Clone, Default)]
+pub struct Registers {
+    regs    : HashMap<char, Register>,
+    unnamed : Option<char>,
+}
+
+impl Registers {
+    pub fn is_valid_name(c : char) -> bool {
+        c.is_ascii_alphanumeric() || c == '"' || c == '-' || c == '_'
+    }
+
+    /*
+     * The unnamed register '"' refers to whichever register was written
+     * last; the black hole register '_' is always empty.
+     */
+    pub fn get(&self, name : char) -> Option<&Register> {
+        match name {
+            '"' => self.unnamed.and_then(|r| self.regs.get(&r)),
+            '_' => None,
+             c  => self.regs.get(&c.to_ascii_lowercase()),
+        }
+    }
+
+    /*
+     * An uppercase name appends to the lowercase register. Appending
+     * lines to characters or the other way around gives lines.
+     */
+    fn write(&mut self, name : char, reg : Register) {
+        let r = name.to_ascii_lowercase();
+        match self.regs.get_mut(&r) {
+            Some(old) if name.is_ascii_uppercase() => {
+                if old.linewise || reg.linewise {
+                    if !old.text.ends_with('\n')    { old.text.push('\n'); }
+                    old.text.push_str(&reg.text);
+                    if !old.text.ends_with('\n')    { old.text.push('\n'); }
+                    old.linewise = true;
+                } else {
+                    old.text.push_str(&reg.text);
+                }
+            },
+            _ => { self.regs.insert(r, reg); },
+        }
+        self.unnamed = Some(r);
+    }
+
+    /*
+     * Recording with q doesn't change what '"' refers to.
+     */
+    pub fn record(&mut self, name : char, text : String) {
+        let unnamed = self.unnamed;
+        self.write(name, Register{ text, linewise : false });
+        self.unnamed = unnamed;
+    }
+
+    pub fn yank(&mut self, name : Option<char>, reg : Register) {
+        match name {
+            Some('_')              => {},
+            None | Some('"')       => self.write('0', reg),
+            Some(c)                => self.write(c, reg),
+        }
+    }
+
+    /*
+     * Deletes of less than a line go to the small delete register '-' unless
+     * a register was named; anything bigger shifts "1 into "2 and so on and
+     * lands in "1 as well. `to_one` sends small deletes to "1 too.
+     */
+    pub fn delete(&mut self, name : Option<char>, reg : Register, small : bool, to_one : bool) {
+        match name {
+            Some('_')              => return,
+            Some(c) if c != '"'    => self.write(c, reg.clone()),
+            _                      => {},
+        }
+
+        if !small || to_one {
+            let unnamed = self.unnamed;
+
+            for i in (1..9).rev() {
+                let from = std::char::from_digit(i, 10).unwrap();
+                let to   = std::char::from_digit(i + 1, 10).unwrap();
+                if let Some(t) = self.regs.remove(&from)    { self.regs.insert(to, t); }
+            }
+            self.write('1', reg.clone());
+
+            /* appending keeps '"' on the register appended to */
+            if name.is_some_and(|c| c.is_ascii_uppercase())    { self.unnamed = unnamed; }
+        }
+
+        if small && name.is_none_or(|c| c == '"') {
+            self.write('-', reg);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+/*
+ * settings.rs
+ * command line settings that change how a command is executed
+ */
+
+#[derive(Clone)]
+pub struct Settings {
+    pub carry_registers : bool,
+    pub buffer          : bool,
+    pub esc_inserts     : bool,
+    pub tabstop         : usize,
+    pub shiftwidth      : usize,
+    pub expandtab       : bool,
+    pub textwidth       : usize,
+    pub matchpairs      : Vec<(char, char)>,
+    pub matchwords      : Vec<Vec<String>>,
+    pub nrformats       : NrFormats,
+}
+
+/* which kinds of numbers Ctrl-A and Ctrl-X recognize, as in Vim's 'nrformats' */
+#[derive(Clone, Copy, Default)]
+pub struct NrFormats {
+    pub alpha    : bool,
+    pub octal    : bool,
+    pub hex      : bool,
+    pub bin      : bool,
+    pub unsigned : bool,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            carry_registers : false,
+            buffer          : false,
+            esc_inserts     : false,
+            tabstop         : 8,
+            shiftwidth      : 8,
+            expandtab       : false,
+            textwidth       : 0,
+            matchpairs      : vec![('(', ')'), ('{', '}'), ('[', ']')],
+            matchwords      : Vec::new(),
+            nrformats       : NrFormats{ octal : true, hex : true, bin : true, ..NrFormats::default() },
+        }
+    }
+}
+
+impl Settings {
+    /*
+     * Applies a Vim-style option from --set, such as "tabstop=4" or
+     * "ts=4". Flags are set by name and cleared with a "no" prefix, as in
+     * "expandtab" and "noet".
+     */
+    pub fn set(&mut self, opt : &str) -> Result<(), String> {
+        let (name, value) = match opt.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None                => (opt, None),
+        };
+
+        let number = |min : usize| -> Result<usize, String> {
+            match value.map(str::parse::<usize>) {
+                Some(Ok(n)) if n >= min => Ok(n),
+                _                       => Err(format!("'{}' needs a number of at least {}", name, min)),
+            }
+        };
+
+        let flag = |on : bool| -> Result<bool, String> {
+            match value {
+                None    => Ok(on),
+                Some(_) => Err(format!("'{}' doesn't take a value", name)),
+            }
+        };
+
+        match name {
+            "tabstop" | "ts"       => self.tabstop = number(1)?,
+            "shiftwidth" | "sw"    => self.shiftwidth = number(0)?,
+            "expandtab" | "et"     => self.expandtab = flag(true)?,
+            "noexpandtab" | "noet" => self.expandtab = flag(false)?,
+            "textwidth" | "tw"     => self.textwidth = number(0)?,
+            "matchpairs" | "mps"   => self.matchpairs = parse_pairs(value.unwrap_or(""))
+                                          .ok_or(format!("'{}' needs pairs like (:),[:]", name))?,
+            "matchwords" | "mw"    => self.matchwords = parse_words(value.unwrap_or(""))
+                                          .ok_or(format!("'{}' needs word groups like if:else:fi,begin:end", name))?,
+            "nrformats" | "nf"     => self.nrformats = parse_formats(value.unwrap_or(""))
+                                          .ok_or(format!("'{}' needs formats from alpha,octal,hex,bin,unsigned", name))?,
+            _                      => return Err(format!("unknown option '{}'", name)),
+        }
+        Ok(())
+    }
+}
+
+/* "(:),[:]" as in Vim's 'matchpairs' */
+fn parse_pairs(value : &str) -> Option<Vec<(char, char)>> {
+    value.split(',').filter(|p| !p.is_empty()).map(|p| {
+        let chars : Vec<char> = p.chars().collect();
+        match chars[..] {
+            [open, ':', close] if open != close => Some((open, close)),
+            _                                   => None,
+        }
+    }).collect()
+}
+
+/*
+ * Groups of words that % cycles through, like matchit's b:match_words:
+ * "if:else:fi,begin:end" pairs the first word of each group with the last
+ * and goes through the ones in between in order.
+ */
+fn parse_words(value : &str) -> Option<Vec<Vec<String>>> {
+    value.split(',').filter(|g| !g.is_empty()).map(|g| {
+        let words : Vec<String> = g.split(':').map(String::from).collect();
+        let ok = words.len() >= 2
+              && words.iter().all(|w| !w.is_empty() && w.chars().all(|c| c.is_alphanumeric() || c == '_'));
+        if ok { Some(words) } else { None }
+    }).collect()
+}
+
+/* "bin,octal,hex" as in Vim's 'nrformats'; an empty value leaves only decimal */
+fn parse_formats(value : &str) -> Option<NrFormats> {
+    let mut formats = NrFormats::default();
+    for f in value.split(',').filter(|f| !f.is_empty()) {
+        match f {
+            "alpha"    => formats.alpha    = true,
+            "octal"    => formats.octal    = true,
+            "hex"      => formats.hex      = true,
+            "bin"      => formats.bin      = true,
+            "unsigned" => formats.unsigned = true,
+            _          => return None,
+        }
+    }
+    Some(formats)
+}